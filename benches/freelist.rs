@@ -0,0 +1,40 @@
+#![feature(test)]
+
+extern crate allocators;
+extern crate test;
+
+use allocators::{Allocator, FreeList};
+use test::Bencher;
+
+const BLOCKS: usize = 1024;
+
+// allocates every block in a burst, touching each in turn, then frees them
+// in reverse so the free list is restored to its original order.
+fn sequential_burst(b: &mut Bencher, alloc: FreeList<allocators::HeapAllocator>) {
+    let mut blocks = Vec::with_capacity(BLOCKS);
+    b.iter(|| {
+        for i in 0..BLOCKS {
+            let block = unsafe { alloc.allocate_raw(64, 8).unwrap() };
+            unsafe { *(block.ptr() as *mut usize) = i };
+            blocks.push(block);
+        }
+        let mut sum = 0;
+        for block in &blocks {
+            sum += unsafe { *(block.ptr() as *const usize) };
+        }
+        test::black_box(sum);
+        while let Some(block) = blocks.pop() {
+            unsafe { alloc.deallocate_raw(block) };
+        }
+    });
+}
+
+#[bench]
+fn sequential_reverse_order(b: &mut Bencher) {
+    sequential_burst(b, FreeList::new(64, BLOCKS).unwrap());
+}
+
+#[bench]
+fn sequential_ascending_order(b: &mut Bencher) {
+    sequential_burst(b, FreeList::new(64, BLOCKS).unwrap().with_ascending_order());
+}
@@ -0,0 +1,31 @@
+#![feature(test)]
+
+extern crate allocators;
+extern crate test;
+
+use allocators::{Allocator, Reset, Scoped};
+use test::Bencher;
+
+const ALLOCATIONS: usize = 1024;
+
+#[bench]
+fn allocate_checked(b: &mut Bencher) {
+    let alloc = Scoped::new(ALLOCATIONS * 8).unwrap();
+    b.iter(|| {
+        for _ in 0..ALLOCATIONS {
+            test::black_box(unsafe { alloc.allocate_raw(8, 8) }.is_ok());
+        }
+        unsafe { alloc.reset() };
+    });
+}
+
+#[bench]
+fn allocate_unchecked(b: &mut Bencher) {
+    let alloc = Scoped::new(ALLOCATIONS * 8).unwrap();
+    b.iter(|| {
+        for _ in 0..ALLOCATIONS {
+            test::black_box(unsafe { alloc.allocate_raw_unchecked(8, 8) }.is_ok());
+        }
+        unsafe { alloc.reset() };
+    });
+}
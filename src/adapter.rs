@@ -0,0 +1,155 @@
+//! An adapter letting allocators back the standard library's collections.
+
+use std::alloc::{AllocError, Allocator as StdAllocator, Layout};
+use std::ptr::{self, NonNull};
+
+use super::{Allocator, Block};
+
+/// Wraps a reference to an allocator to implement the standard library's `Allocator`
+/// trait, so it can back collections like `Vec::new_in` and `Box::new_in` directly.
+///
+/// The standard library requires that moving an allocator doesn't invalidate the blocks
+/// it handed out, which doesn't hold for allocators storing their memory inline, like
+/// `InlineScoped`. Wrapping a reference satisfies it for every allocator, and lets several
+/// collections share one. Errors are reported as the standard library's opaque `AllocError`.
+///
+/// # Examples
+/// ```rust
+/// #![feature(allocator_api)]
+/// use allocators::{Scoped, StdAllocatorAdapter};
+///
+/// let arena = Scoped::new(1024).unwrap();
+/// let mut vec = Vec::new_in(StdAllocatorAdapter::new(&arena));
+/// vec.extend_from_slice(&[1, 2, 3]);
+/// ```
+///
+/// An inline arena can't be moved into a collection, since moving the collection
+/// would move the arena's buffer out from under it:
+///
+/// ```rust,compile_fail
+/// #![feature(allocator_api)]
+/// use allocators::{InlineScoped, StdAllocatorAdapter};
+///
+/// let vec = Vec::<u8, _>::new_in(StdAllocatorAdapter::new(InlineScoped::<256>::new()));
+/// ```
+pub struct StdAllocatorAdapter<'a, A: 'a> {
+    alloc: &'a A,
+}
+
+impl<'a, A: Allocator> StdAllocatorAdapter<'a, A> {
+    /// Wraps a reference to the allocator supplied.
+    pub fn new(alloc: &'a A) -> Self {
+        StdAllocatorAdapter { alloc: alloc }
+    }
+
+    /// Get a reference to the allocator this wraps.
+    pub fn inner(&self) -> &'a A {
+        self.alloc
+    }
+
+    // resizes a block through `reallocate_raw_aligned`, which takes care of any change
+    // in alignment.
+    unsafe fn resize(&self,
+                     ptr: NonNull<u8>,
+                     old_layout: Layout,
+                     new_layout: Layout)
+                     -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        } else if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(dangling(new_layout));
+        }
+
+        let block = Block::new(ptr.as_ptr(), old_layout.size(), old_layout.align());
+        match self.alloc.reallocate_raw_aligned(block, new_layout.size(), new_layout.align()) {
+            Ok(block) => Ok(block.as_non_null_slice()),
+            // the block is still the caller's.
+            Err(_) => Err(AllocError),
+        }
+    }
+}
+
+// an empty slice aligned as the layout asks.
+fn dangling(layout: Layout) -> NonNull<[u8]> {
+    unsafe { NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(layout.align() as *mut u8, 0)) }
+}
+
+unsafe impl<'a, A: Allocator> StdAllocator for StdAllocatorAdapter<'a, A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match unsafe { self.alloc.allocate_raw(layout.size(), layout.align()) } {
+            Ok(block) => {
+                if block.is_empty() {
+                    Ok(dangling(layout))
+                } else {
+                    Ok(block.as_non_null_slice())
+                }
+            }
+            Err(_) => Err(AllocError),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() != 0 {
+            self.alloc.deallocate_raw(Block::new(ptr.as_ptr(), layout.size(), layout.align()))
+        }
+    }
+
+    unsafe fn grow(&self,
+                   ptr: NonNull<u8>,
+                   old_layout: Layout,
+                   new_layout: Layout)
+                   -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(&self,
+                     ptr: NonNull<u8>,
+                     old_layout: Layout,
+                     new_layout: Layout)
+                     -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn backs_vec() {
+        let arena = Scoped::new(256).unwrap();
+        let mut vec = Vec::new_in(StdAllocatorAdapter::new(&arena));
+        for i in 0..32u32 {
+            vec.push(i);
+        }
+        assert_eq!(vec.iter().sum::<u32>(), 496);
+        assert!(arena.owns_ptr(vec.as_ptr() as *mut u8));
+        vec.shrink_to_fit();
+        assert_eq!(&vec[..3], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn inline_arena_by_reference() {
+        let arena = InlineScoped::<256>::new();
+        let mut vec = Vec::new_in(StdAllocatorAdapter::new(&arena));
+        vec.extend_from_slice(&[1u8, 2, 3]);
+        let moved = vec;
+        assert_eq!(&moved[..], &[1, 2, 3]);
+        assert!(arena.owns_ptr(moved.as_ptr() as *mut u8));
+    }
+
+    #[test]
+    fn backs_box() {
+        let alloc = FreeList::new(16, 1).unwrap();
+        let adapter = StdAllocatorAdapter::new(&alloc);
+        let boxed = Box::new_in([7u64; 2], &adapter);
+        assert_eq!(alloc.free(), 0);
+        assert!(Box::try_new_in(0u64, &adapter).is_err());
+        drop(boxed);
+        assert_eq!(alloc.free(), 1);
+
+        let unit = Box::new_in((), &adapter);
+        assert_eq!(*unit, ());
+    }
+}
@@ -0,0 +1,266 @@
+//! A typed arena handing out indices rather than references.
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Index, IndexMut};
+use std::ptr;
+
+use super::{Allocator, Block, Error};
+
+/// A handle to a value stored in a `TypedArena`.
+///
+/// Handles carry the generation of the slot they refer to, which changes whenever
+/// the slot is reused. A handle to a removed value never resolves to another value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ArenaIndex {
+    index: usize,
+    generation: usize,
+}
+
+// a value along with the generation of its slot, or a vacant slot.
+struct Slot<T> {
+    generation: usize,
+    value: Option<T>,
+}
+
+/// An arena storing values of a single type, which are referred to by `ArenaIndex`
+/// handles instead of references.
+///
+/// Since handles don't borrow the arena, values can refer to each other freely,
+/// which makes this well suited to graphs and trees. Values are stored in chunks
+/// of a fixed number of slots, drawn from the allocator as needed. Removing a value
+/// frees its slot for reuse; the rest are dropped along with the arena.
+pub struct TypedArena<'a, T, A: 'a + ?Sized + Allocator> {
+    allocator: &'a A,
+    chunk_len: usize,
+    chunks: RefCell<Vec<Block<'a>>>,
+    // the number of slots initialized, whether occupied or vacant.
+    slots: Cell<usize>,
+    len: Cell<usize>,
+    vacant: RefCell<Vec<usize>>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, A: ?Sized + Allocator> TypedArena<'a, T, A> {
+    /// Creates a new, empty arena, which will allocate space for `chunk_len` values at a time.
+    ///
+    /// # Panics
+    /// Panics if `chunk_len` is zero.
+    pub fn new(chunk_len: usize, alloc: &'a A) -> Self {
+        assert!(chunk_len != 0, "Arena chunks must hold at least one value.");
+        TypedArena {
+            allocator: alloc,
+            chunk_len: chunk_len,
+            chunks: RefCell::new(Vec::new()),
+            slots: Cell::new(0),
+            len: Cell::new(0),
+            vacant: RefCell::new(Vec::new()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Moves a value into the arena, returning a handle to it.
+    ///
+    /// Slots freed by `remove` are reused first.
+    /// On failure, the value is given back along with the error.
+    pub fn alloc(&self, val: T) -> Result<ArenaIndex, (Error, T)> {
+        if let Some(index) = self.vacant.borrow_mut().pop() {
+            let slot = unsafe { &mut *self.slot(index) };
+            slot.value = Some(val);
+            self.len.set(self.len.get() + 1);
+            return Ok(ArenaIndex {
+                index: index,
+                generation: slot.generation,
+            });
+        }
+
+        let index = self.slots.get();
+        if index == self.chunks.borrow().len() * self.chunk_len {
+            if let Err(err) = self.add_chunk() {
+                return Err((err, val));
+            }
+        }
+
+        unsafe {
+            ptr::write(self.slot(index),
+                       Slot {
+                           generation: 0,
+                           value: Some(val),
+                       })
+        };
+        self.slots.set(index + 1);
+        self.len.set(self.len.get() + 1);
+        Ok(ArenaIndex {
+            index: index,
+            generation: 0,
+        })
+    }
+
+    /// Get a reference to the value behind a handle, or `None` if it has been removed.
+    pub fn get(&self, index: ArenaIndex) -> Option<&T> {
+        match self.live_slot(index) {
+            Some(slot) => unsafe { (*slot).value.as_ref() },
+            None => None,
+        }
+    }
+
+    /// Get a mutable reference to the value behind a handle, or `None` if it has been removed.
+    pub fn get_mut(&mut self, index: ArenaIndex) -> Option<&mut T> {
+        match self.live_slot(index) {
+            Some(slot) => unsafe { (*slot).value.as_mut() },
+            None => None,
+        }
+    }
+
+    /// Moves the value behind a handle out of the arena, freeing its slot.
+    ///
+    /// Returns `None` if it has already been removed.
+    /// The handle, and any copies of it, won't resolve afterwards.
+    pub fn remove(&mut self, index: ArenaIndex) -> Option<T> {
+        let slot = match self.live_slot(index) {
+            Some(slot) => unsafe { &mut *slot },
+            None => return None,
+        };
+
+        let val = slot.value.take();
+        if val.is_some() {
+            slot.generation = slot.generation.wrapping_add(1);
+            self.vacant.borrow_mut().push(index.index);
+            self.len.set(self.len.get() - 1);
+        }
+        val
+    }
+
+    /// The number of values in the arena.
+    pub fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Whether the arena is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len.get() == 0
+    }
+
+    fn add_chunk(&self) -> Result<(), Error> {
+        let align = mem::align_of::<Slot<T>>();
+        let size = match mem::size_of::<Slot<T>>().checked_mul(self.chunk_len) {
+            Some(size) => size,
+            None => return Err(Error::OutOfMemory {
+                requested: usize::MAX,
+                align: align,
+            }),
+        };
+        match unsafe { self.allocator.allocate_raw(size, align) } {
+            Ok(block) => {
+                self.chunks.borrow_mut().push(block);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // a pointer to the slot at idx, which must be within an allocated chunk.
+    fn slot(&self, idx: usize) -> *mut Slot<T> {
+        let chunks = self.chunks.borrow();
+        let chunk = &chunks[idx / self.chunk_len];
+        unsafe { (chunk.ptr() as *mut Slot<T>).offset((idx % self.chunk_len) as isize) }
+    }
+
+    // a pointer to the slot a handle refers to, if it's of the handle's generation.
+    fn live_slot(&self, index: ArenaIndex) -> Option<*mut Slot<T>> {
+        if index.index >= self.slots.get() {
+            return None;
+        }
+        let slot = self.slot(index.index);
+        if unsafe { (*slot).generation } == index.generation {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Index<ArenaIndex> for TypedArena<'a, T, A> {
+    type Output = T;
+
+    /// # Panics
+    /// Panics if the value has been removed.
+    fn index(&self, index: ArenaIndex) -> &T {
+        self.get(index).expect("Stale arena index.")
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> IndexMut<ArenaIndex> for TypedArena<'a, T, A> {
+    fn index_mut(&mut self, index: ArenaIndex) -> &mut T {
+        self.get_mut(index).expect("Stale arena index.")
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Drop for TypedArena<'a, T, A> {
+    fn drop(&mut self) {
+        for idx in 0..self.slots.get() {
+            unsafe { ptr::drop_in_place(self.slot(idx)) };
+        }
+        for chunk in self.chunks.borrow_mut().drain(..) {
+            if chunk.is_empty() {
+                continue;
+            }
+            unsafe { self.allocator.deallocate_raw(chunk) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    struct Node {
+        val: i32,
+        next: Option<ArenaIndex>,
+    }
+
+    #[test]
+    fn linked_by_index() {
+        let arena = TypedArena::new(2, &HEAP);
+        let mut next = None;
+        for val in 0..5 {
+            next = Some(arena.alloc(Node { val: val, next: next }).ok().unwrap());
+        }
+        assert_eq!(arena.len(), 5);
+
+        let mut vals = Vec::new();
+        while let Some(idx) = next {
+            vals.push(arena[idx].val);
+            next = arena[idx].next;
+        }
+        assert_eq!(vals, vec![4, 3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn chunk_exhaustion() {
+        // room for one chunk of two slots, but not two.
+        let alloc = Scoped::new(64).unwrap();
+        let arena = TypedArena::new(2, &alloc);
+        let a = arena.alloc(1u64).ok().unwrap();
+        arena.alloc(2u64).ok().unwrap();
+        assert!(arena.alloc(3u64).is_err());
+        assert_eq!(arena[a], 1);
+    }
+
+    #[test]
+    fn stale_index() {
+        let mut arena = TypedArena::new(4, &HEAP);
+        let first = arena.alloc("first".to_string()).ok().unwrap();
+        let second = arena.alloc("second".to_string()).ok().unwrap();
+        assert_eq!(arena.remove(first), Some("first".to_string()));
+        assert_eq!(arena.remove(first), None);
+
+        // the new value reuses the first's slot.
+        let third = arena.alloc("third".to_string()).ok().unwrap();
+        assert_eq!(arena.len(), 2);
+        assert!(arena.get(first).is_none());
+        assert_eq!(arena.get(third).unwrap(), "third");
+        assert_eq!(arena[second], "second");
+    }
+}
@@ -1,12 +1,16 @@
 use std::any::Any;
 use std::borrow::{Borrow, BorrowMut};
 use std::marker::{PhantomData, Unsize};
-use std::mem;
+use std::mem::{self, MaybeUninit};
 use std::ops::{CoerceUnsized, Deref, DerefMut, InPlace, Placer};
 use std::ops::Place as StdPlace;
-use std::ptr::Unique;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr::{self, NonNull, Unique};
+use std::slice;
+use std::task::{Context, Poll};
 
-use super::{Allocator, Block};
+use super::{Allocator, Block, Error};
 
 /// An item allocated by a custom allocator.
 pub struct AllocBox<'a, T: 'a + ?Sized, A: 'a + ?Sized + Allocator> {
@@ -20,16 +24,207 @@ impl<'a, T: ?Sized, A: ?Sized + Allocator> AllocBox<'a, T, A> {
     /// Consumes this allocated value, yielding the value it manages.
     pub fn take(self) -> T where T: Sized {
         let val = unsafe { ::std::ptr::read(self.item.as_ptr()) };
-        let block = Block::new(self.item.as_ptr() as *mut u8, self.size, self.align);
-        unsafe { self.allocator.deallocate_raw(block) };
+        unsafe { self.free() };
         mem::forget(self);
         val
     }
 
+    /// Consumes this allocated value, yielding the value it manages along with a place
+    /// holding on to its memory.
+    ///
+    /// This is like `take`, but keeps the memory, so a new value can be emplaced into
+    /// it without a round trip through the allocator. The value is moved out, so it's
+    /// dropped by the caller as usual, and the place only ever holds uninitialized memory:
+    /// dropping the place frees the memory without dropping anything.
+    pub fn into_place(self) -> (T, Place<'a, T, A>) where T: Sized {
+        let val = unsafe { ptr::read(self.item.as_ptr()) };
+        let block = if self.size == 0 {
+            Block::empty()
+        } else {
+            Block::new(self.item.as_ptr() as *mut u8, self.size, self.align)
+        };
+        let place = Place {
+            allocator: self.allocator,
+            block: block,
+            _marker: PhantomData,
+        };
+        mem::forget(self);
+        (val, place)
+    }
+
+    /// Consumes this allocated value, moving the value it manages directly into `dst`.
+    ///
+    /// This avoids the intermediate copy `take` makes, which matters for large values.
+    /// Any value already in `dst` is overwritten without being dropped;
+    /// after the call, `dst` is initialized.
+    pub fn take_into(self, dst: &mut MaybeUninit<T>) where T: Sized {
+        unsafe {
+            ptr::copy_nonoverlapping(self.item.as_ptr(), dst.as_mut_ptr(), 1);
+            self.free();
+        }
+        mem::forget(self);
+    }
+
+    /// Converts this into a box of an unsized type, like a trait object or slice.
+    ///
+    /// This does the same as an unsizing coercion, for generic code where one won't happen.
+    pub fn unsize<U: ?Sized + 'a>(self) -> AllocBox<'a, U, A> where T: Unsize<U> {
+        self
+    }
+
+    /// The size of the block of memory this manages.
+    pub fn allocated_size(&self) -> usize {
+        self.size
+    }
+
+    /// The alignment of the block of memory this manages.
+    pub fn allocated_align(&self) -> usize {
+        self.align
+    }
+
+    /// Whether this and another box were allocated by the same allocator.
+    ///
+    /// This compares the allocators by address. References to zero-sized allocators,
+    /// like the heap, may or may not compare equal.
+    pub fn same_allocator<U: ?Sized>(&self, other: &AllocBox<U, A>) -> bool {
+        ptr::eq(self.allocator, other.allocator)
+    }
+
     /// Gets a handle to the block of memory this manages.
     pub unsafe fn as_block(&self) -> Block {
         Block::new(self.item.as_ptr() as *mut u8, self.size, self.align)
     }
+
+    /// Pins the value this manages in place.
+    ///
+    /// An `AllocBox` never moves its value: it stays at the same address in the
+    /// allocator's memory until it's dropped. Moving it out requires consuming the box
+    /// with `take`, which can't be done through the `Pin`, and `DerefMut` only hands out
+    /// `&mut T`, which `Pin` withholds unless `T: Unpin`.
+    ///
+    /// # Safety
+    /// Pinning also promises that the value's memory isn't reused until it has been
+    /// dropped. Many allocators reclaim memory wholesale, without dropping anything in it:
+    /// a `Scoped` when it's reset or dropped, or when an inner scope ends. So if the box
+    /// is leaked, e.g. with `mem::forget`, the allocator must never reclaim its block.
+    /// This always holds for allocators which only reuse memory freed explicitly,
+    /// like the heap.
+    pub unsafe fn into_pin(boxed: Self) -> Pin<Self> {
+        Pin::new_unchecked(boxed)
+    }
+
+    // frees the memory without dropping the value.
+    // zero-sized values were never allocated, so there's nothing to free.
+    unsafe fn free(&self) {
+        if self.size != 0 {
+            self.allocator.deallocate_raw(self.as_block());
+        }
+    }
+}
+
+// The box itself is just a pointer, so moving it never moves the value.
+impl<'a, T: ?Sized, A: ?Sized + Allocator> Unpin for AllocBox<'a, T, A> {}
+
+impl<'a, T, A: ?Sized + Allocator> AllocBox<'a, [T], A> {
+    /// Reconstructs a boxed slice from a pointer to its first element, its length,
+    /// and the allocator it was allocated with.
+    ///
+    /// When dropped, the box will drop all `len` elements and then free a block of
+    /// `len * size_of::<T>()` bytes with an alignment of `align_of::<T>()`.
+    ///
+    /// # Safety
+    /// The memory must have been allocated by `allocator` with exactly that size and alignment,
+    /// as done by `into_raw_parts`, and hold `len` initialized values.
+    pub unsafe fn from_raw_parts(ptr: *mut T, len: usize, allocator: &'a A) -> Self {
+        AllocBox {
+            item: Unique::new(slice::from_raw_parts_mut(ptr, len)),
+            size: len * mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
+            allocator: allocator,
+        }
+    }
+
+    /// Consumes the box, yielding a pointer to its first element and its length.
+    /// Neither the elements nor the memory are freed.
+    ///
+    /// Use `from_raw_parts` to turn these back into a box.
+    ///
+    /// # Panics
+    /// Panics if the box was allocated with a stricter alignment than `T`'s, e.g. by
+    /// `Allocator::allocate_aligned_slice`, since `from_raw_parts` couldn't recover
+    /// its memory from the parts.
+    pub fn into_raw_parts(self) -> (*mut T, usize) {
+        assert!(self.align == mem::align_of::<T>(),
+                "Can't take apart a box aligned more strictly than its elements.");
+        let items = unsafe { &mut *self.item.as_ptr() };
+        mem::forget(self);
+        (items.as_mut_ptr(), items.len())
+    }
+
+    /// Get the slice this manages.
+    pub fn as_slice(&self) -> &[T] {
+        &**self
+    }
+
+    /// Get the slice this manages mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut **self
+    }
+}
+
+impl<'a, A: ?Sized + Allocator> AllocBox<'a, [u8], A> {
+    /// Resizes the boxed bytes to `new_len` through the allocator's `reallocate_zeroed`,
+    /// zeroing any bytes added.
+    ///
+    /// The bytes may move, depending on the allocator. If it fails, the box is given
+    /// back untouched along with the error.
+    pub fn realloc(self, new_len: usize) -> Result<Self, (Error, Self)> {
+        let allocator = self.allocator;
+        let result = unsafe {
+            if self.size == 0 {
+                allocator.allocate_zeroed(new_len, self.align)
+            } else if new_len == 0 {
+                self.free();
+                Ok(Block::empty())
+            } else {
+                let block = Block::new(self.item.as_ptr() as *mut u8, self.size, self.align);
+                match allocator.reallocate_zeroed(block, new_len) {
+                    Ok(block) => Ok(block),
+                    Err((err, block)) => {
+                        mem::forget(block);
+                        Err(err)
+                    }
+                }
+            }
+        };
+
+        match result {
+            Ok(block) => {
+                let ptr = if block.is_empty() { NonNull::dangling().as_ptr() } else { block.ptr() };
+                let align = self.align;
+                mem::forget((self, block));
+                Ok(AllocBox {
+                    item: unsafe { Unique::new(slice::from_raw_parts_mut(ptr, new_len)) },
+                    size: new_len,
+                    align: align,
+                    allocator: allocator,
+                })
+            }
+            Err(err) => Err((err, self)),
+        }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator, const N: usize> AllocBox<'a, [T; N], A> {
+    /// Get the array this manages as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        &**self
+    }
+
+    /// Get the array this manages as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut **self
+    }
 }
 
 impl<'a, T: ?Sized, A: ?Sized + Allocator> Deref for AllocBox<'a, T, A> {
@@ -52,21 +247,31 @@ impl<'a, T: ?Sized + Unsize<U>, U: ?Sized, A: ?Sized + Allocator> CoerceUnsized<
 impl<'a, A: ?Sized + Allocator> AllocBox<'a, Any, A> {
     /// Attempts to downcast this `AllocBox` to a concrete type.
     pub fn downcast<T: Any>(self) -> Result<AllocBox<'a, T, A>, AllocBox<'a, Any, A>> {
-        use std::raw::TraitObject;
         if self.is::<T>() {
-            let obj: TraitObject = unsafe { mem::transmute::<*mut Any, TraitObject>(self.item.as_ptr()) };
-            let new_allocated = AllocBox {
-                item: unsafe { Unique::new(obj.data as *mut T) },
-                size: self.size,
-                align: self.align,
-                allocator: self.allocator,
-            };
-            mem::forget(self);
-            Ok(new_allocated)
+            Ok(unsafe { self.downcast_unchecked() })
         } else {
             Err(self)
         }
     }
+
+    /// Downcasts this `AllocBox` to a concrete type without checking it.
+    ///
+    /// # Safety
+    /// The contained value must be of type `T`. Calling this with any other type
+    /// is undefined behavior.
+    pub unsafe fn downcast_unchecked<T: Any>(self) -> AllocBox<'a, T, A> {
+        use std::raw::TraitObject;
+        debug_assert!(self.is::<T>());
+        let obj: TraitObject = mem::transmute::<*mut Any, TraitObject>(self.item.as_ptr());
+        let new_allocated = AllocBox {
+            item: Unique::new(obj.data as *mut T),
+            size: self.size,
+            align: self.align,
+            allocator: self.allocator,
+        };
+        mem::forget(self);
+        new_allocated
+    }
 }
 
 impl<'a, T: ?Sized, A: ?Sized + Allocator> Borrow<T> for AllocBox<'a, T, A> {
@@ -81,21 +286,183 @@ impl<'a, T: ?Sized, A: ?Sized + Allocator> BorrowMut<T> for AllocBox<'a, T, A> {
     }
 }
 
+// Futures which aren't `Unpin` must be pinned first, with the unsafe `into_pin`
+// or `allocate_pinned`: `Pin<AllocBox<F, A>>` is then a future itself.
+impl<'a, F: ?Sized + Future + Unpin, A: ?Sized + Allocator> Future for AllocBox<'a, F, A> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        Pin::new(&mut **self).poll(cx)
+    }
+}
+
 impl<'a, T: ?Sized, A: ?Sized + Allocator> Drop for AllocBox<'a, T, A> {
+    /// Drops the value and frees its memory.
+    ///
+    /// For slices, the pointer carries the length, so every element is dropped,
+    /// and `size` covers all of them.
     #[inline]
     fn drop(&mut self) {
         use std::intrinsics::drop_in_place;
+        debug_assert_eq!(mem::size_of_val(&**self), self.size);
         unsafe {
             drop_in_place(self.item.as_ptr());
-            self.allocator.deallocate_raw(Block::new(self.item.as_ptr() as *mut u8, self.size, self.align));
+            self.free();
+        }
+
+    }
+}
+
+/// A value paired with a hook to run on it just before it is dropped.
+///
+/// This is created by `Allocator::allocate_with_drop`. The hook is stored alongside the
+/// value, in the same allocation, and runs exactly once when this is dropped, before the
+/// value's own destructor. Taking this out of its box with `AllocBox::take` keeps the hook
+/// armed; use `into_inner` or `Hooked::take` to get the bare value and discard the hook.
+pub struct Hooked<T, F: FnOnce(&mut T)> {
+    val: T,
+    hook: Option<F>,
+}
+
+impl<T, F: FnOnce(&mut T)> Hooked<T, F> {
+    /// Pairs a value with a hook.
+    pub fn new(val: T, hook: F) -> Self {
+        Hooked {
+            val: val,
+            hook: Some(hook),
+        }
+    }
+
+    /// Takes the value back out, discarding the hook without running it.
+    pub fn into_inner(self) -> T {
+        let mut this = mem::ManuallyDrop::new(self);
+        unsafe {
+            ptr::drop_in_place(&mut this.hook);
+            ptr::read(&this.val)
+        }
+    }
+
+    /// Takes the value out of a box, discarding the hook without running it.
+    pub fn take<A: ?Sized + Allocator>(this: AllocBox<Hooked<T, F>, A>) -> T {
+        this.take().into_inner()
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> Deref for Hooked<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.val
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> DerefMut for Hooked<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.val
+    }
+}
+
+impl<T, F: FnOnce(&mut T)> Drop for Hooked<T, F> {
+    fn drop(&mut self) {
+        if let Some(hook) = self.hook.take() {
+            hook(&mut self.val);
         }
+    }
+}
+
+/// An item allocated by an allocator which it owns, rather than borrows.
+///
+/// This is like `Box`, with a pluggable allocator: since it doesn't borrow anything, it can
+/// be returned from the function that created it, allocator and all. When dropped, the
+/// value is dropped and its memory freed before the allocator itself is dropped.
+pub struct OwnedBox<T, A: Allocator> {
+    item: Unique<T>,
+    size: usize,
+    align: usize,
+    allocator: A,
+}
 
+impl<T, A: Allocator> OwnedBox<T, A> {
+    /// Moves a value into memory from the allocator supplied, which the box then owns.
+    ///
+    /// On failure, the value is given back along with the error.
+    ///
+    /// # Safety
+    /// The memory the allocator hands out must stay valid when the allocator is moved.
+    /// This holds for allocators whose memory lives elsewhere, like `Scoped` or `FreeList`,
+    /// but not for those storing it inline, like `InlineScoped`.
+    pub unsafe fn new(val: T, alloc: A) -> Result<Self, (Error, T)> {
+        let (size, align) = (mem::size_of::<T>(), mem::align_of::<T>());
+        let ptr = if size == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            match alloc.allocate_raw(size, align) {
+                Ok(block) => block.ptr() as *mut T,
+                Err(err) => return Err((err, val)),
+            }
+        };
+
+        ptr::write(ptr, val);
+        Ok(OwnedBox {
+            item: Unique::new(ptr),
+            size: size,
+            align: align,
+            allocator: alloc,
+        })
+    }
+
+    /// Get a reference to the allocator this owns.
+    pub fn allocator(&self) -> &A {
+        &self.allocator
+    }
+}
+
+impl<T, A: Allocator> Deref for OwnedBox<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.item.as_ptr() }
+    }
+}
+
+impl<T, A: Allocator> DerefMut for OwnedBox<T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.item.as_ptr() }
     }
 }
 
+impl<T, A: Allocator> Drop for OwnedBox<T, A> {
+    /// The allocator is dropped after this returns, once the memory is freed.
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.item.as_ptr());
+            if self.size != 0 {
+                self.allocator.deallocate_raw(Block::new(self.item.as_ptr() as *mut u8, self.size, self.align));
+            }
+        }
+    }
+}
+
+// wraps a block already allocated from `alloc` in a place for a `T`.
+// the block must be empty if `T` is zero-sized, and fit a `T` otherwise.
+pub unsafe fn place_from_block<'a, A: ?Sized + Allocator, T>(alloc: &'a A, block: Block<'a>) -> Place<'a, T, A> {
+    Place {
+        allocator: alloc,
+        block: block,
+        _marker: PhantomData,
+    }
+}
 
 pub fn make_place<A: ?Sized + Allocator, T>(alloc: &A) -> Result<Place<T, A>, super::Error> {
     let (size, align) = (mem::size_of::<T>(), mem::align_of::<T>());
+    // zero-sized values live at a dangling, well-aligned pointer and need no memory.
+    if size == 0 {
+        return Ok(Place {
+            allocator: alloc,
+            block: Block::empty(),
+            _marker: PhantomData,
+        });
+    }
     match unsafe { alloc.allocate_raw(size, align) } {
         Ok(block) => {
             Ok(Place {
@@ -117,6 +484,23 @@ pub struct Place<'a, T: 'a, A: 'a + ?Sized + Allocator> {
     _marker: PhantomData<T>,
 }
 
+impl<'a, T: 'a, A: 'a + ?Sized + Allocator> Place<'a, T, A> {
+    /// Initializes the place with a function which may fail.
+    ///
+    /// The function is given a pointer to the uninitialized memory. If it returns `Ok`,
+    /// it must have written a value there, which is then boxed. If it returns an error,
+    /// the memory is freed and the error passed on; nothing will be dropped.
+    pub fn try_finalize<E, F>(mut self, init: F) -> Result<AllocBox<'a, T, A>, E>
+        where F: FnOnce(*mut T) -> Result<(), E>
+    {
+        match init(self.pointer()) {
+            Ok(()) => Ok(unsafe { self.finalize() }),
+            // the destructor frees the memory without dropping anything.
+            Err(err) => Err(err),
+        }
+    }
+}
+
 impl<'a, T: 'a, A: 'a + ?Sized + Allocator> Placer<T> for Place<'a, T, A> {
     type Place = Self;
     fn make_place(self) -> Self {
@@ -126,11 +510,11 @@ impl<'a, T: 'a, A: 'a + ?Sized + Allocator> Placer<T> for Place<'a, T, A> {
 
 impl<'a, T: 'a, A: 'a + ?Sized + Allocator> InPlace<T> for Place<'a, T, A> {
     type Owner = AllocBox<'a, T, A>;
-    unsafe fn finalize(self) -> Self::Owner {
+    unsafe fn finalize(mut self) -> Self::Owner {
         let allocated = AllocBox {
-            item: Unique::new(self.block.ptr() as *mut T),
+            item: Unique::new(self.pointer()),
             size: self.block.size(),
-            align: self.block.align(),
+            align: if self.block.is_empty() { mem::align_of::<T>() } else { self.block.align() },
             allocator: self.allocator,
         };
 
@@ -141,7 +525,11 @@ impl<'a, T: 'a, A: 'a + ?Sized + Allocator> InPlace<T> for Place<'a, T, A> {
 
 impl<'a, T: 'a, A: 'a + ?Sized + Allocator> StdPlace<T> for Place<'a, T, A> {
     fn pointer(&mut self) -> *mut T {
-        self.block.ptr() as *mut T
+        if self.block.is_empty() {
+            NonNull::dangling().as_ptr()
+        } else {
+            self.block.ptr() as *mut T
+        }
     }
 }
 
@@ -153,9 +541,112 @@ impl<'a, T: 'a, A: 'a + ?Sized + Allocator> Drop for Place<'a, T, A> {
         // method was never called, the expression
         // to create the value failed and the memory at the
         // pointer is still uninitialized, which we don't want to drop.
+        if !self.block.is_empty() {
+            unsafe { self.allocator.deallocate_raw(mem::replace(&mut self.block, Block::empty())) };
+        }
+
+    }
+}
+
+// Creates a boxed slice of `len` initialized values at `ptr`, to be freed with
+// a size of `len * size_of::<T>()` and the given alignment.
+pub unsafe fn slice_from_raw<'a, T, A: ?Sized + Allocator>(ptr: *mut T,
+                                                            len: usize,
+                                                            align: usize,
+                                                            alloc: &'a A)
+                                                            -> AllocBox<'a, [T], A> {
+    let mut boxed = AllocBox::from_raw_parts(ptr, len, alloc);
+    boxed.align = align;
+    boxed
+}
+
+// Frees a partially filled slice if filling it panics, dropping the filled prefix.
+struct PartialSlice<'a, T, A: 'a + ?Sized + Allocator> {
+    ptr: *mut T,
+    filled: usize,
+    block: Block<'a>,
+    allocator: &'a A,
+}
+
+impl<'a, T, A: ?Sized + Allocator> Drop for PartialSlice<'a, T, A> {
+    fn drop(&mut self) {
         unsafe {
-            self.allocator.deallocate_raw(mem::replace(&mut self.block, Block::empty()));
+            ptr::drop_in_place(slice::from_raw_parts_mut(self.ptr, self.filled));
+            if !self.block.is_empty() {
+                self.allocator.deallocate_raw(mem::replace(&mut self.block, Block::empty()));
+            }
         }
+    }
+}
+
+pub fn collect_in<'a, T, I, A>(alloc: &'a A, iter: I) -> Result<AllocBox<'a, [T], A>, super::Error>
+    where I: ExactSizeIterator<Item = T>,
+          A: ?Sized + Allocator
+{
+    collect_aligned_in(alloc, iter, mem::align_of::<T>())
+}
+
+// Like `collect_in`, but aligns the slice to `align`, which must be a power of two
+// at least as large as the alignment of `T`.
+pub fn collect_aligned_in<'a, T, I, A>(alloc: &'a A,
+                                       iter: I,
+                                       align: usize)
+                                       -> Result<AllocBox<'a, [T], A>, super::Error>
+    where I: ExactSizeIterator<Item = T>,
+          A: ?Sized + Allocator
+{
+    let len = iter.len();
+    let size = match len.checked_mul(mem::size_of::<T>()) {
+        Some(size) => size,
+        None => return Err(super::Error::OutOfMemory {
+            requested: usize::MAX,
+            align: align,
+        }),
+    };
+
+    match unsafe { alloc.allocate_raw(size, align) } {
+        Ok(block) => {
+            let ptr = if block.is_empty() { align as *mut T } else { block.ptr() as *mut T };
+            let mut partial = PartialSlice {
+                ptr: ptr,
+                filled: 0,
+                block: block,
+                allocator: alloc,
+            };
+            for val in iter.take(len) {
+                unsafe { ptr::write(ptr.offset(partial.filled as isize), val) };
+                partial.filled += 1;
+            }
+            if partial.filled < len {
+                panic!("Iterator yielded {} items, fewer than the {} it reported.", partial.filled, len);
+            }
+
+            mem::forget(partial);
+            Ok(unsafe { slice_from_raw(ptr, len, align, alloc) })
+        }
+        Err(err) => Err(err),
+    }
+}
 
+// Moves the elements of a `Vec` into a new slice from the allocator with one bulk copy.
+pub fn absorb_vec<'a, T, A>(alloc: &'a A, mut vec: Vec<T>) -> Result<AllocBox<'a, [T], A>, (super::Error, Vec<T>)>
+    where A: ?Sized + Allocator
+{
+    let (len, align) = (vec.len(), mem::align_of::<T>());
+    // the vector already holds this many elements, so the size can't overflow.
+    let size = len * mem::size_of::<T>();
+
+    match unsafe { alloc.allocate_raw(size, align) } {
+        Ok(block) => {
+            let ptr = if block.is_empty() { NonNull::dangling().as_ptr() } else { block.ptr() as *mut T };
+            unsafe {
+                ptr::copy_nonoverlapping(vec.as_ptr(), ptr, len);
+                // the elements have moved, so the vector only frees its buffer.
+                vec.set_len(0);
+            }
+            drop(vec);
+            Ok(unsafe { slice_from_raw(ptr, len, align, alloc) })
+        }
+        Err(err) => Err((err, vec)),
     }
-}
\ No newline at end of file
+}
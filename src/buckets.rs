@@ -0,0 +1,186 @@
+//! An allocator which serves requests from a set of size classes.
+
+use super::{Allocator, Error, Block, BlockOwner, HeapAllocator, Report, HEAP};
+use super::freelist::FreeList;
+
+/// The utilization of one of a `Buckets` allocator's size classes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ClassUsage {
+    /// The size of the blocks in this class.
+    pub size: usize,
+    /// The number of blocks currently allocated.
+    pub in_use: usize,
+    /// The total number of blocks in this class.
+    pub capacity: usize,
+}
+
+/// A general purpose small-object allocator, which keeps a `FreeList` for each
+/// of a fixed set of size classes.
+///
+/// Every request is served by the smallest class its size and alignment fit in.
+/// Requests too large for any class, or made when the fitting class is exhausted,
+/// fall through to the backing allocator.
+///
+/// Only blocks served by one of the classes are reported as owned by `owns_block`.
+pub struct Buckets<'a, A: 'a + Allocator> {
+    alloc: &'a A,
+    classes: Vec<FreeList<'a, A>>,
+}
+
+impl Buckets<'static, HeapAllocator> {
+    /// Creates a new `Buckets` backed by the heap.
+    /// See `new_from` for the requirements on the size classes.
+    pub fn new(classes: &[(usize, usize)]) -> Result<Self, Error> {
+        Buckets::new_from(HEAP, classes)
+    }
+}
+
+impl<'a, A: Allocator> Buckets<'a, A> {
+    /// Creates a new `Buckets` backed by another allocator, given `(size, count)` pairs
+    /// for each size class. The sizes must be sorted in increasing order,
+    /// with no duplicates, and fulfill the requirements of `FreeList::new_from`.
+    pub fn new_from(alloc: &'a A, classes: &[(usize, usize)]) -> Result<Self, Error> {
+        if classes.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(Error::AllocatorSpecific("Size classes not sorted and distinct.".into()));
+        }
+
+        let mut lists = Vec::with_capacity(classes.len());
+        for &(size, count) in classes {
+            match FreeList::new_from(alloc, size, count) {
+                Ok(list) => lists.push(list),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Buckets {
+            alloc: alloc,
+            classes: lists,
+        })
+    }
+
+    /// The utilization of each size class, from smallest to largest.
+    pub fn utilization(&self) -> Vec<ClassUsage> {
+        self.classes
+            .iter()
+            .map(|class| {
+                ClassUsage {
+                    size: class.block_size(),
+                    in_use: class.capacity() - class.free(),
+                    capacity: class.capacity(),
+                }
+            })
+            .collect()
+    }
+
+    // the smallest class the request fits in.
+    fn class_for(&self, size: usize, align: usize) -> Option<&FreeList<'a, A>> {
+        self.classes.iter().find(|class| size <= class.block_size() && align <= class.block_align())
+    }
+
+    fn owner(&self, block: &Block) -> Option<&FreeList<'a, A>> {
+        self.classes.iter().find(|class| class.owns_block(block))
+    }
+}
+
+unsafe impl<'a, A: Allocator> Allocator for Buckets<'a, A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if size == 0 {
+            return Ok(Block::empty());
+        }
+
+        match self.class_for(size, align) {
+            Some(class) => {
+                match class.allocate_raw(size, align) {
+                    Ok(block) => Ok(block),
+                    Err(_) => self.alloc.allocate_raw(size, align),
+                }
+            }
+            None => self.alloc.allocate_raw(size, align),
+        }
+    }
+
+    unsafe fn reallocate_raw<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+        if new_size == 0 {
+            self.deallocate_raw(block);
+            return Ok(Block::empty());
+        } else if block.is_empty() {
+            return Err((Error::UnsupportedAlignment, block));
+        }
+
+        match self.owner(&block) {
+            Some(class) if new_size <= class.block_size() => class.reallocate_raw(block, new_size),
+            Some(_) => {
+                let align = block.align();
+                super::copy_reallocate(self, block, new_size, align)
+            }
+            None => {
+                if self.class_for(new_size, block.align()).is_some() {
+                    // move the block into a class if it now fits in one.
+                    let align = block.align();
+                    super::copy_reallocate(self, block, new_size, align)
+                } else {
+                    self.alloc.reallocate_raw(block, new_size)
+                }
+            }
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        match self.owner(&block) {
+            Some(class) => class.deallocate_raw(block),
+            None => self.alloc.deallocate_raw(block),
+        }
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Buckets");
+        for class in &self.classes {
+            report.merge(class.report());
+        }
+        report
+    }
+}
+
+impl<'a, A: Allocator> BlockOwner for Buckets<'a, A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.classes.iter().any(|class| class.owns_ptr(ptr))
+    }
+
+    /// Frees every block served by one of the size classes.
+    /// Blocks from the backing allocator aren't affected.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        for class in &self.classes {
+            if let Err(err) = class.deallocate_all() {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::ClassUsage;
+
+    #[test]
+    fn routes_to_smallest_class() {
+        let alloc = Buckets::new(&[(16, 2), (64, 2)]).ok().unwrap();
+        let small = alloc.allocate([0u8; 12]).unwrap();
+        let medium = alloc.allocate([0u8; 48]).unwrap();
+        let large = alloc.allocate([0u8; 256]).unwrap();
+
+        assert!(alloc.owns_block(&unsafe { small.as_block() }));
+        assert!(alloc.owns_block(&unsafe { medium.as_block() }));
+        assert!(!alloc.owns_block(&unsafe { large.as_block() }));
+        assert_eq!(alloc.utilization(),
+                   vec![ClassUsage { size: 16, in_use: 1, capacity: 2 },
+                        ClassUsage { size: 64, in_use: 1, capacity: 2 }]);
+    }
+
+    #[test]
+    fn unsorted_classes() {
+        assert!(Buckets::new(&[(64, 1), (16, 1)]).is_err());
+        assert!(Buckets::new(&[(16, 1), (16, 1)]).is_err());
+    }
+}
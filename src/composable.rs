@@ -1,18 +1,36 @@
 //! This module contains some composable building blocks to build allocator chains.
 
-use super::{Allocator, Error, Block, BlockOwner};
+use std::backtrace::Backtrace as StdBacktrace;
+use std::cell::{Cell, RefCell};
+#[cfg(feature = "bench")]
+use std::cell::UnsafeCell;
+use std::cmp;
+use std::collections::HashMap;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Allocator, CapacityAware, Error, Block, BlockOwner, Report};
 
 /// This allocator always fails.
 /// It will panic if you try to deallocate with it.
 pub struct NullAllocator;
 
 unsafe impl Allocator for NullAllocator {
-    unsafe fn allocate_raw(&self, _size: usize, _align: usize) -> Result<Block, Error> {
-        Err(Error::OutOfMemory)
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        Err(Error::OutOfMemory {
+            requested: size,
+            align: align,
+        })
     }
 
-    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, _new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
-        Err((Error::OutOfMemory, block))
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let align = block.align();
+        Err((Error::OutOfMemory {
+            requested: new_size,
+            align: align,
+        }, block))
     }
 
     unsafe fn deallocate_raw(&self, _block: Block) {
@@ -20,10 +38,20 @@ unsafe impl Allocator for NullAllocator {
     }
 }
 
+impl CapacityAware for NullAllocator {
+    fn remaining(&self) -> Option<usize> {
+        Some(0)
+    }
+}
+
 impl BlockOwner for NullAllocator {
-    fn owns_block(&self, _block: &Block) -> bool {
+    fn owns_ptr(&self, _ptr: *mut u8) -> bool {
         false
     }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// This allocator has a main and a fallback allocator.
@@ -32,6 +60,7 @@ impl BlockOwner for NullAllocator {
 pub struct Fallback<M: BlockOwner, F: BlockOwner> {
     main: M,
     fallback: F,
+    on_exhausted: Option<Box<Fn() -> bool + Send + Sync>>,
 }
 
 impl<M: BlockOwner, F: BlockOwner> Fallback<M, F> {
@@ -40,15 +69,40 @@ impl<M: BlockOwner, F: BlockOwner> Fallback<M, F> {
         Fallback {
             main: main,
             fallback: fallback,
+            on_exhausted: None,
+        }
+    }
+
+    /// Sets a function to be called when both allocators fail.
+    ///
+    /// This is a chance to free up memory, e.g. by clearing caches.
+    /// If it returns true, the allocation is attempted once more.
+    /// The function must be `Send` and `Sync`, so the `Fallback` stays so as well.
+    pub fn with_on_exhausted<C>(mut self, on_exhausted: C) -> Self
+        where C: Fn() -> bool + Send + Sync + 'static
+    {
+        self.on_exhausted = Some(Box::new(on_exhausted));
+        self
+    }
+
+    unsafe fn try_allocate(&self, size: usize, align: usize) -> Result<Block, Error> {
+        match self.main.allocate_raw(size, align) {
+            Ok(block) => Ok(block),
+            Err(_) => self.fallback.allocate_raw(size, align),
         }
     }
 }
 
 unsafe impl<M: BlockOwner, F: BlockOwner> Allocator for Fallback<M, F> {
     unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
-        match self.main.allocate_raw(size, align) {
+        match self.try_allocate(size, align) {
             Ok(block) => Ok(block),
-            Err(_) => self.fallback.allocate_raw(size, align),
+            Err(err) => {
+                match self.on_exhausted {
+                    Some(ref on_exhausted) if on_exhausted() => self.try_allocate(size, align),
+                    _ => Err(err),
+                }
+            }
         }
     }
 
@@ -69,11 +123,121 @@ unsafe impl<M: BlockOwner, F: BlockOwner> Allocator for Fallback<M, F> {
             self.fallback.deallocate_raw(block);
         }
     }
+
+    fn provides_zeroed(&self) -> bool {
+        self.main.provides_zeroed() && self.fallback.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Fallback");
+        report.merge(self.main.report());
+        report.merge(self.fallback.report());
+        report
+    }
 }
 
 impl<M: BlockOwner, F: BlockOwner> BlockOwner for Fallback<M, F> {
-    fn owns_block(&self, block: &Block) -> bool {
-        self.main.owns_block(block) || self.fallback.owns_block(block)
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.main.owns_ptr(ptr) || self.fallback.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        match self.main.deallocate_all() {
+            Ok(()) => self.fallback.deallocate_all(),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// This allocator spreads allocations across several backends, taking turns
+/// as to which serves the next one.
+///
+/// This balances the pressure on independent pools, e.g. ones on different NUMA nodes.
+/// If the backend whose turn it is fails, the others are tried in order.
+/// Reallocations and deallocations go to the backend owning the block.
+pub struct RoundRobin<A: BlockOwner> {
+    backends: Vec<A>,
+    next: AtomicUsize,
+}
+
+impl<A: BlockOwner> RoundRobin<A> {
+    /// Create a new `RoundRobin` over the backends supplied.
+    ///
+    /// # Panics
+    /// Panics if there are no backends.
+    pub fn new(backends: Vec<A>) -> Self {
+        assert!(!backends.is_empty(), "RoundRobin needs at least one backend.");
+        RoundRobin {
+            backends: backends,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The backends allocations are spread across.
+    pub fn backends(&self) -> &[A] {
+        &self.backends
+    }
+
+    fn owner(&self, block: &Block) -> Option<&A> {
+        self.backends.iter().find(|backend| backend.owns_block(block))
+    }
+}
+
+unsafe impl<A: BlockOwner> Allocator for RoundRobin<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let len = self.backends.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % len;
+        let mut result = Err(Error::OutOfMemory {
+            requested: size,
+            align: align,
+        });
+        for i in 0..len {
+            result = self.backends[(start + i) % len].allocate_raw(size, align);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        match self.owner(&block) {
+            Some(backend) => backend.reallocate_raw(block, new_size),
+            None => Err((Error::AllocatorSpecific("No backend owns this block.".into()), block)),
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if let Some(backend) = self.owner(&block) {
+            backend.deallocate_raw(block);
+        }
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.backends.iter().all(|alloc| alloc.provides_zeroed())
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("RoundRobin");
+        for backend in &self.backends {
+            report.merge(backend.report());
+        }
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for RoundRobin<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.backends.iter().any(|backend| backend.owns_ptr(ptr))
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        for backend in &self.backends {
+            if let Err(err) = backend.deallocate_all() {
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -145,16 +309,1401 @@ unsafe impl<A: Allocator, L: ProxyLogger> Allocator for Proxy<A, L> {
         self.logger.deallocate(&block);
         self.alloc.deallocate_raw(block);
     }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Proxy");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner, L: ProxyLogger> BlockOwner for Proxy<A, L> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.alloc.deallocate_all()
+    }
+}
+
+/// This wraps an allocator and a logger like `Proxy`, but only passes one in every
+/// `rate` events on to the logger.
+///
+/// This keeps the overhead of logging low enough for hot paths. Since the events are
+/// sampled at a fixed rate, totals can be estimated by multiplying what the logger
+/// saw by the rate.
+pub struct SamplingProxy<A, L> {
+    alloc: A,
+    logger: L,
+    rate: usize,
+    events: Cell<usize>,
+}
+
+impl<A: Allocator, L: ProxyLogger> SamplingProxy<A, L> {
+    /// Create a new sampling proxy, logging one in every `rate` events.
+    ///
+    /// # Panics
+    /// Panics if the rate is zero.
+    pub fn new(alloc: A, logger: L, rate: usize) -> Self {
+        assert!(rate != 0, "Sample rate must be non-zero.");
+        SamplingProxy {
+            alloc: alloc,
+            logger: logger,
+            rate: rate,
+            events: Cell::new(0),
+        }
+    }
+
+    /// The number of events per logged event.
+    pub fn rate(&self) -> usize {
+        self.rate
+    }
+
+    // counts an event, returning whether it should be logged.
+    fn sample(&self) -> bool {
+        let events = self.events.get();
+        self.events.set(events.wrapping_add(1));
+        events % self.rate == 0
+    }
+}
+
+unsafe impl<A: Allocator, L: ProxyLogger> Allocator for SamplingProxy<A, L> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let result = self.alloc.allocate_raw(size, align);
+        if self.sample() {
+            match result {
+                Ok(ref block) => self.logger.allocate_success(block),
+                Err(ref err) => self.logger.allocate_fail(err, size, align),
+            }
+        }
+        result
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old_copy = Block::new(block.ptr(), block.size(), block.align());
+        let result = self.alloc.reallocate_raw(block, new_size);
+        if self.sample() {
+            match result {
+                Ok(ref new_block) => self.logger.reallocate_success(&old_copy, new_block),
+                Err((ref err, ref old)) => self.logger.reallocate_fail(err, old, new_size),
+            }
+        }
+        result
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if self.sample() {
+            self.logger.deallocate(&block);
+        }
+        self.alloc.deallocate_raw(block);
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("SamplingProxy");
+        report.merge(self.alloc.report());
+        report
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::super::*;
+impl<A: BlockOwner, L: ProxyLogger> BlockOwner for SamplingProxy<A, L> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
 
-    #[test]
-    #[should_panic]
-    fn null_allocate() {
-        let alloc = NullAllocator;
-        alloc.allocate(1i32).unwrap();
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.alloc.deallocate_all()
+    }
+}
+
+/// The cache line size `CacheAligned` uses by default.
+pub const DEFAULT_CACHE_LINE_SIZE: usize = 64;
+
+/// This wraps an allocator, raising the alignment of every allocation to
+/// at least the size of a cache line, so that no two allocations share one.
+/// This avoids false sharing between data used by different threads.
+///
+/// Requests which are already more aligned are passed through unchanged.
+pub struct CacheAligned<A> {
+    alloc: A,
+    line_size: usize,
+}
+
+impl<A: Allocator> CacheAligned<A> {
+    /// Create a new `CacheAligned` with the default cache line size.
+    pub fn new(alloc: A) -> Self {
+        CacheAligned::with_line_size(alloc, DEFAULT_CACHE_LINE_SIZE)
+    }
+
+    /// Create a new `CacheAligned` with the given cache line size.
+    ///
+    /// # Panics
+    /// Panics if the line size isn't a power of two.
+    pub fn with_line_size(alloc: A, line_size: usize) -> Self {
+        assert!(line_size.is_power_of_two(), "Cache line size must be a power of two.");
+        CacheAligned {
+            alloc: alloc,
+            line_size: line_size,
+        }
+    }
+
+    /// The cache line size allocations are aligned to.
+    pub fn line_size(&self) -> usize {
+        self.line_size
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for CacheAligned<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        // the block records the raised alignment, so it's used for
+        // reallocation and deallocation as well.
+        self.alloc.allocate_raw(size, cmp::max(align, self.line_size))
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        self.alloc.reallocate_raw(block, new_size)
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        self.alloc.deallocate_raw(block)
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("CacheAligned");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for CacheAligned<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.alloc.deallocate_all()
+    }
+}
+
+/// This wraps an allocator, recording the call stack of every allocation
+/// until it is freed. This makes it possible to find out where leaked memory came from.
+///
+/// Capturing a backtrace on every allocation is very slow, and the backtraces take up
+/// plenty of memory themselves, so this should only be used while debugging.
+pub struct Backtrace<A> {
+    alloc: A,
+    traces: RefCell<HashMap<usize, StdBacktrace>>,
+}
+
+impl<A: Allocator> Backtrace<A> {
+    /// Create a new `Backtrace` wrapping the allocator supplied.
+    pub fn new(alloc: A) -> Self {
+        Backtrace {
+            alloc: alloc,
+            traces: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The backtraces of all allocations which haven't been freed yet.
+    pub fn leaks(&self) -> Vec<String> {
+        self.traces.borrow().values().map(|trace| trace.to_string()).collect()
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for Backtrace<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        match self.alloc.allocate_raw(size, align) {
+            Ok(block) => {
+                if !block.is_empty() {
+                    self.traces.borrow_mut().insert(block.ptr() as usize, StdBacktrace::force_capture());
+                }
+                Ok(block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old_ptr = block.ptr() as usize;
+        match self.alloc.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                // the allocation keeps the backtrace of where it was first made.
+                let mut traces = self.traces.borrow_mut();
+                if let Some(trace) = traces.remove(&old_ptr) {
+                    if !new_block.is_empty() {
+                        traces.insert(new_block.ptr() as usize, trace);
+                    }
+                }
+                Ok(new_block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        self.traces.borrow_mut().remove(&(block.ptr() as usize));
+        self.alloc.deallocate_raw(block)
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Backtrace");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for Backtrace<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        match self.alloc.deallocate_all() {
+            Ok(()) => {
+                self.traces.borrow_mut().clear();
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// This wraps an allocator, keeping a registry of all outstanding allocations.
+///
+/// This is a lighter-weight alternative to `Backtrace` for taking a census of live memory,
+/// but every allocation and deallocation still costs a search of the registry,
+/// so it's meant for debugging and profiling.
+pub struct TrackedAllocator<A> {
+    alloc: A,
+    // the pointer, size, and alignment of every live block.
+    live: RefCell<Vec<(*mut u8, usize, usize)>>,
+}
+
+impl<A: Allocator> TrackedAllocator<A> {
+    /// Create a new `TrackedAllocator` wrapping the allocator supplied.
+    pub fn new(alloc: A) -> Self {
+        TrackedAllocator {
+            alloc: alloc,
+            live: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The pointer, size, and alignment of every allocation which hasn't been freed yet,
+    /// as of the time this is called.
+    pub fn live(&self) -> impl Iterator<Item = (*mut u8, usize, usize)> {
+        self.live.borrow().clone().into_iter()
+    }
+
+    fn forget(&self, ptr: *mut u8) {
+        let mut live = self.live.borrow_mut();
+        if let Some(idx) = live.iter().position(|&(live_ptr, _, _)| live_ptr == ptr) {
+            live.swap_remove(idx);
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for TrackedAllocator<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        match self.alloc.allocate_raw(size, align) {
+            Ok(block) => {
+                if !block.is_empty() {
+                    self.live.borrow_mut().push((block.ptr(), size, align));
+                }
+                Ok(block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old_ptr = block.ptr();
+        match self.alloc.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                self.forget(old_ptr);
+                if !new_block.is_empty() {
+                    self.live.borrow_mut().push((new_block.ptr(), new_block.size(), new_block.align()));
+                }
+                Ok(new_block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        self.forget(block.ptr());
+        self.alloc.deallocate_raw(block)
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("TrackedAllocator");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for TrackedAllocator<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    /// Frees every block in the registry, one by one.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        for (ptr, size, align) in self.live.borrow_mut().drain(..) {
+            self.alloc.deallocate_raw(Block::new(ptr, size, align));
+        }
+        Ok(())
+    }
+}
+
+/// This wraps an allocator, holding back deallocations until `flush` is called.
+///
+/// This is useful for deferred reclamation schemes, where freed memory may still be
+/// read by others until some later point. Pending deallocations are flushed
+/// when the wrapper is dropped.
+pub struct Deferred<A: Allocator> {
+    alloc: A,
+    // the pointer, size, and alignment of every block waiting to be freed.
+    pending: RefCell<Vec<(*mut u8, usize, usize)>>,
+}
+
+impl<A: Allocator> Deferred<A> {
+    /// Create a new `Deferred` wrapping the allocator supplied.
+    pub fn new(alloc: A) -> Self {
+        Deferred {
+            alloc: alloc,
+            pending: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Frees every block deallocated since the last flush.
+    pub fn flush(&self) {
+        let pending = mem::replace(&mut *self.pending.borrow_mut(), Vec::new());
+        for (ptr, size, align) in pending {
+            unsafe { self.alloc.deallocate_raw(Block::new(ptr, size, align)) };
+        }
+    }
+
+    /// The number of deallocations waiting for a flush.
+    pub fn pending_count(&self) -> usize {
+        self.pending.borrow().len()
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for Deferred<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        self.alloc.allocate_raw(size, align)
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        self.alloc.reallocate_raw(block, new_size)
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if !block.is_empty() {
+            self.pending.borrow_mut().push((block.ptr(), block.size(), block.align()));
+        }
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Deferred");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for Deferred<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    /// Discards the pending deallocations, since they're covered by this.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.pending.borrow_mut().clear();
+        self.alloc.deallocate_all()
+    }
+}
+
+impl<A: Allocator> Drop for Deferred<A> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// This wraps an allocator, failing any allocation which would take the total
+/// number of bytes outstanding beyond a cap.
+pub struct Capped<A> {
+    alloc: A,
+    cap: usize,
+    used: Cell<usize>,
+}
+
+impl<A: Allocator> Capped<A> {
+    /// Create a new `Capped` allowing up to `cap` bytes outstanding at once.
+    pub fn new(alloc: A, cap: usize) -> Self {
+        Capped {
+            alloc: alloc,
+            cap: cap,
+            used: Cell::new(0),
+        }
+    }
+
+    /// The number of bytes allowed outstanding at once.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    /// The number of bytes currently outstanding.
+    pub fn used(&self) -> usize {
+        self.used.get()
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for Capped<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if size > self.cap - self.used.get() {
+            return Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            });
+        }
+        match self.alloc.allocate_raw(size, align) {
+            Ok(block) => {
+                self.used.set(self.used.get() + block.size());
+                Ok(block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old_size = block.size();
+        if new_size > old_size && new_size - old_size > self.cap - self.used.get() {
+            let align = block.align();
+            return Err((Error::OutOfMemory {
+                requested: new_size,
+                align: align,
+            }, block));
+        }
+        match self.alloc.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                self.used.set(self.used.get() - old_size + new_block.size());
+                Ok(new_block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        self.used.set(self.used.get() - block.size());
+        self.alloc.deallocate_raw(block);
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Capped");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: Allocator> CapacityAware for Capped<A> {
+    /// The number of bytes left under the cap. The inner allocator may run out sooner.
+    fn remaining(&self) -> Option<usize> {
+        Some(self.cap - self.used.get())
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for Capped<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        match self.alloc.deallocate_all() {
+            Ok(()) => {
+                self.used.set(0);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A builder for stacking the wrappers in this module around an allocator.
+///
+/// Each method wraps the allocator built so far, and `build` yields the result.
+/// The wrappers are applied inside out, so the last one added sees requests first.
+/// Nothing is boxed: the result is the same type as composing the wrappers by hand.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Allocator, AllocatorBuilder, FreeList, Scoped};
+///
+/// let overflow = Scoped::new(4096).unwrap();
+/// // 64-byte blocks, falling back to the scoped allocator,
+/// // with at most 1KB outstanding, each allocation on its own cache line.
+/// let alloc = AllocatorBuilder::new(FreeList::new(64, 8).unwrap())
+///     .with_fallback(overflow)
+///     .capped_at(1024)
+///     .cache_aligned()
+///     .build();
+/// let val = alloc.allocate([0u8; 100]).unwrap();
+/// assert_eq!(&*val as *const _ as usize % 64, 0);
+/// ```
+pub struct AllocatorBuilder<A> {
+    alloc: A,
+}
+
+impl<A: Allocator> AllocatorBuilder<A> {
+    /// Starts building on the allocator supplied.
+    pub fn new(alloc: A) -> Self {
+        AllocatorBuilder { alloc: alloc }
+    }
+
+    /// Logs all activity with the logger supplied, using a `Proxy`.
+    pub fn with_logging<L: ProxyLogger>(self, logger: L) -> AllocatorBuilder<Proxy<A, L>> {
+        AllocatorBuilder::new(Proxy::new(self.alloc, logger))
+    }
+
+    /// Falls back to another allocator when this one fails, using a `Fallback`.
+    pub fn with_fallback<O: BlockOwner>(self, other: O) -> AllocatorBuilder<Fallback<A, O>>
+        where A: BlockOwner
+    {
+        AllocatorBuilder::new(Fallback::new(self.alloc, other))
+    }
+
+    /// Caps the number of bytes outstanding at once, using a `Capped`.
+    pub fn capped_at(self, bytes: usize) -> AllocatorBuilder<Capped<A>> {
+        AllocatorBuilder::new(Capped::new(self.alloc, bytes))
+    }
+
+    /// Aligns all allocations to cache lines, using a `CacheAligned`.
+    pub fn cache_aligned(self) -> AllocatorBuilder<CacheAligned<A>> {
+        AllocatorBuilder::new(CacheAligned::new(self.alloc))
+    }
+
+    /// Yields the allocator built.
+    pub fn build(self) -> A {
+        self.alloc
+    }
+}
+
+/// This wraps an allocator, checking that it is used correctly when debug assertions
+/// are enabled. Without them, this simply forwards to the allocator.
+///
+/// Every call panics if:
+///
+/// - the alignment requested isn't a power of two.
+/// - the size requested overflows when rounded up to the alignment.
+/// - a block being reallocated or deallocated wasn't allocated by this,
+///   or was already freed.
+/// - a block being reallocated or deallocated has a different size or alignment
+///   than it was allocated with.
+///
+/// Keeping track of live blocks costs a map update on every call.
+pub struct Checked<A> {
+    alloc: A,
+    // the size and alignment of every live block, by address.
+    #[cfg(debug_assertions)]
+    live: RefCell<HashMap<usize, (usize, usize)>>,
+}
+
+impl<A: Allocator> Checked<A> {
+    /// Create a new `Checked` wrapping the allocator supplied.
+    pub fn new(alloc: A) -> Self {
+        Checked {
+            alloc: alloc,
+            #[cfg(debug_assertions)]
+            live: RefCell::new(HashMap::new()),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_request(&self, size: usize, align: usize) {
+        if !align.is_power_of_two() {
+            panic!("Alignment {} is not a power of two.", align);
+        }
+        if size.checked_add(align - 1).is_none() {
+            panic!("Size {} overflows when aligned to {}.", size, align);
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn check_live(&self, block: &Block) {
+        match self.live.borrow().get(&(block.ptr() as usize)) {
+            Some(&(size, align)) => {
+                if (size, align) != (block.size(), block.align()) {
+                    panic!("Block {:?} has size {} and align {}, but was allocated with \
+                            size {} and align {}.",
+                           block.ptr(),
+                           block.size(),
+                           block.align(),
+                           size,
+                           align);
+                }
+            }
+            None => panic!("Block {:?} is unknown or was already freed.", block.ptr()),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn record(&self, block: &Block) {
+        if !block.is_empty() {
+            self.live.borrow_mut().insert(block.ptr() as usize, (block.size(), block.align()));
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for Checked<A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        #[cfg(debug_assertions)]
+        self.check_request(size, align);
+        let result = self.alloc.allocate_raw(size, align);
+        #[cfg(debug_assertions)]
+        {
+            if let Ok(ref block) = result {
+                self.record(block);
+            }
+        }
+        result
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        #[cfg(debug_assertions)]
+        {
+            if !block.is_empty() {
+                self.check_live(&block);
+            }
+            self.check_request(new_size, cmp::max(block.align(), 1));
+        }
+        #[cfg(debug_assertions)]
+        let old_ptr = block.ptr() as usize;
+        let result = self.alloc.reallocate_raw(block, new_size);
+        #[cfg(debug_assertions)]
+        {
+            if let Ok(ref new_block) = result {
+                self.live.borrow_mut().remove(&old_ptr);
+                self.record(new_block);
+            }
+        }
+        result
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        #[cfg(debug_assertions)]
+        {
+            if !block.is_empty() {
+                self.check_live(&block);
+                self.live.borrow_mut().remove(&(block.ptr() as usize));
+            }
+        }
+        self.alloc.deallocate_raw(block)
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.alloc.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Checked");
+        report.merge(self.alloc.report());
+        report
+    }
+}
+
+impl<A: BlockOwner> BlockOwner for Checked<A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.alloc.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        match self.alloc.deallocate_all() {
+            Ok(()) => {
+                #[cfg(debug_assertions)]
+                self.live.borrow_mut().clear();
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The size of the buffer shared by all `ScratchAllocator`s.
+#[cfg(feature = "bench")]
+pub const SCRATCH_SIZE: usize = 1 << 16;
+
+#[cfg(feature = "bench")]
+#[repr(align(4096))]
+struct ScratchBuffer(UnsafeCell<[u8; SCRATCH_SIZE]>);
+
+#[cfg(feature = "bench")]
+unsafe impl Sync for ScratchBuffer {}
+
+#[cfg(feature = "bench")]
+static SCRATCH: ScratchBuffer = ScratchBuffer(UnsafeCell::new([0; SCRATCH_SIZE]));
+
+/// An allocator for benchmarks, which serves every allocation from the start of
+/// a single static buffer of `SCRATCH_SIZE` bytes.
+///
+/// All allocations alias the same memory, both with each other and with those of
+/// every other `ScratchAllocator`, so any value written may be overwritten at any time.
+/// This makes it possible to measure the cost of code around allocations without
+/// the cost of allocating. Deallocation does nothing.
+///
+/// Requests larger than the buffer, or aligned to more than a page, fail.
+/// This is only available with the `bench` feature.
+#[cfg(feature = "bench")]
+pub struct ScratchAllocator {
+    _private: (),
+}
+
+#[cfg(feature = "bench")]
+impl ScratchAllocator {
+    /// Create a new `ScratchAllocator`.
+    ///
+    /// # Safety
+    /// Nothing allocated from it may be relied on to keep its value,
+    /// or to be unaffected by writes to anything else allocated from it.
+    pub unsafe fn new() -> Self {
+        ScratchAllocator { _private: () }
+    }
+}
+
+#[cfg(feature = "bench")]
+unsafe impl Allocator for ScratchAllocator {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        if size == 0 {
+            Ok(Block::empty())
+        } else if align > 4096 {
+            Err(Error::AlignmentTooLarge {
+                requested: align,
+                max: 4096,
+            })
+        } else if size > SCRATCH_SIZE {
+            Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            })
+        } else {
+            Ok(Block::new(SCRATCH.0.get() as *mut u8, size, align))
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        if block.is_empty() {
+            Err((Error::UnsupportedAlignment, block))
+        } else if new_size == 0 {
+            Ok(Block::empty())
+        } else if new_size > SCRATCH_SIZE {
+            let align = block.align();
+            Err((Error::OutOfMemory {
+                requested: new_size,
+                align: align,
+            }, block))
+        } else {
+            Ok(Block::new(block.ptr(), new_size, block.align()))
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, _block: Block) {}
+}
+
+/// This wraps two allocators, mirroring every allocation made from the primary
+/// with a shadow copy of the same size from the backup.
+///
+/// Blocks handed out come from the primary. An allocator only hands out memory and
+/// never sees what's written to it, so writes can't be mirrored as they happen: they
+/// only reach the backup when `sync` is called with the block. This is deliberate,
+/// making each checkpoint explicit. After a `sync`, `verify` can check the block against
+/// its shadow to detect corruption, and `restore` can copy the shadow back over it.
+/// Every allocation takes up twice the memory it otherwise would.
+pub struct Mirror<A, B> {
+    primary: A,
+    backup: B,
+    // the shadow of each live block, keyed by the address of the block.
+    shadows: RefCell<HashMap<usize, (*mut u8, usize, usize)>>,
+}
+
+impl<A: Allocator, B: Allocator> Mirror<A, B> {
+    /// Create a new `Mirror` allocating from `primary`, shadowed by `backup`.
+    pub fn new(primary: A, backup: B) -> Self {
+        Mirror {
+            primary: primary,
+            backup: backup,
+            shadows: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get the address of the shadow copy of a block, if it has one.
+    pub fn shadow(&self, block: &Block) -> Option<*mut u8> {
+        self.shadows.borrow().get(&(block.ptr() as usize)).map(|&(ptr, _, _)| ptr)
+    }
+
+    /// Copies the contents of a block into its shadow.
+    /// Does nothing if the block has no shadow.
+    ///
+    /// # Safety
+    /// The block must be a live allocation from this allocator.
+    pub unsafe fn sync(&self, block: &Block) {
+        if let Some(shadow) = self.shadow(block) {
+            ptr::copy_nonoverlapping(block.ptr(), shadow, block.size());
+        }
+    }
+
+    /// Copies the contents of a block's shadow back over it.
+    /// Does nothing if the block has no shadow.
+    ///
+    /// # Safety
+    /// The block must be a live allocation from this allocator.
+    pub unsafe fn restore(&self, block: &Block) {
+        if let Some(shadow) = self.shadow(block) {
+            ptr::copy_nonoverlapping(shadow, block.ptr(), block.size());
+        }
+    }
+
+    /// Whether the contents of a block match its shadow, as of the last `sync`.
+    /// Blocks without a shadow, like empty ones, trivially match.
+    ///
+    /// # Safety
+    /// The block must be a live allocation from this allocator.
+    pub unsafe fn verify(&self, block: &Block) -> bool {
+        match self.shadow(block) {
+            Some(shadow) => {
+                slice::from_raw_parts(block.ptr(), block.size()) ==
+                slice::from_raw_parts(shadow, block.size())
+            }
+            None => true,
+        }
+    }
+}
+
+unsafe impl<A: Allocator, B: Allocator> Allocator for Mirror<A, B> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let block = match self.primary.allocate_raw(size, align) {
+            Ok(block) => block,
+            Err(err) => return Err(err),
+        };
+        if block.is_empty() {
+            return Ok(block);
+        }
+
+        match self.backup.allocate_raw(size, align) {
+            Ok(shadow) => {
+                self.shadows.borrow_mut().insert(block.ptr() as usize, (shadow.ptr(), size, align));
+                Ok(block)
+            }
+            Err(err) => {
+                self.primary.deallocate_raw(block);
+                Err(err)
+            }
+        }
+    }
+
+    /// The shadow is resized first, so that if either allocator fails,
+    /// the block is given back untouched. An empty block has no shadow, so if it's
+    /// reallocated to a non-empty one, a new shadow is allocated after it instead.
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old = self.shadows.borrow_mut().remove(&(block.ptr() as usize));
+        let shadow = match old {
+            Some((ptr, size, align)) => {
+                match self.backup.reallocate_raw(Block::new(ptr, size, align), new_size) {
+                    Ok(shadow) => Some(shadow),
+                    Err((err, _)) => {
+                        self.shadows.borrow_mut().insert(block.ptr() as usize, (ptr, size, align));
+                        return Err((err, block));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let (old_ptr, old_size, old_align) = (block.ptr(), block.size(), block.align());
+        match self.primary.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                if new_block.is_empty() {
+                    return Ok(new_block);
+                }
+                let shadow = match shadow {
+                    Some(shadow) => shadow,
+                    None => {
+                        match self.backup.allocate_raw(new_block.size(), new_block.align()) {
+                            Ok(shadow) => shadow,
+                            Err(err) => {
+                                self.primary.deallocate_raw(new_block);
+                                return Err((err, Block::new(old_ptr, old_size, old_align)));
+                            }
+                        }
+                    }
+                };
+                self.shadows
+                    .borrow_mut()
+                    .insert(new_block.ptr() as usize, (shadow.ptr(), shadow.size(), shadow.align()));
+                Ok(new_block)
+            }
+            Err(err) => {
+                if let Some(shadow) = shadow {
+                    let shadow = match self.backup.reallocate_raw(shadow, old_size) {
+                        Ok(shadow) => shadow,
+                        // the shadow may be left larger than the block, which is harmless.
+                        Err((_, shadow)) => shadow,
+                    };
+                    self.shadows
+                        .borrow_mut()
+                        .insert(old_ptr as usize, (shadow.ptr(), shadow.size(), shadow.align()));
+                }
+                Err(err)
+            }
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if let Some((ptr, size, align)) = self.shadows.borrow_mut().remove(&(block.ptr() as usize)) {
+            self.backup.deallocate_raw(Block::new(ptr, size, align));
+        }
+        self.primary.deallocate_raw(block)
+    }
+
+    fn provides_zeroed(&self) -> bool {
+        self.primary.provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Mirror");
+        report.merge(self.primary.report());
+        report.merge(self.backup.report());
+        report
+    }
+}
+
+impl<A: BlockOwner, B: BlockOwner> BlockOwner for Mirror<A, B> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.primary.owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.shadows.borrow_mut().clear();
+        match self.primary.deallocate_all() {
+            Ok(()) => self.backup.deallocate_all(),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::super::*;
+
+    #[test]
+    #[should_panic]
+    fn null_allocate() {
+        let alloc = NullAllocator;
+        alloc.allocate(1i32).unwrap();
+    }
+
+    #[test]
+    fn retry_once_when_exhausted() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let alloc = Fallback::new(NullAllocator, NullAllocator).with_on_exhausted(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            true
+        });
+
+        assert!(alloc.allocate(1i32).is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retry_after_freeing() {
+        static ARENA: AtomicInlineScoped<16> = AtomicInlineScoped::new();
+
+        // lets the fallback use the arena while the hook can still reach it.
+        struct Shared;
+
+        unsafe impl Allocator for Shared {
+            unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+                ARENA.allocate_raw(size, align)
+            }
+
+            unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+                ARENA.reallocate_raw(block, new_size)
+            }
+
+            unsafe fn deallocate_raw(&self, block: Block) {
+                ARENA.deallocate_raw(block)
+            }
+        }
+
+        impl BlockOwner for Shared {
+            fn owns_ptr(&self, ptr: *mut u8) -> bool {
+                ARENA.owns_ptr(ptr)
+            }
+        }
+
+        fn assert_sync<T: Send + Sync>(_: &T) {}
+
+        let alloc = Fallback::new(Shared, NullAllocator).with_on_exhausted(|| {
+            // frees everything the arena held, so the retry fits.
+            unsafe { ARENA.reset() };
+            true
+        });
+        assert_sync(&alloc);
+
+        let first = alloc.allocate([1u8; 16]).unwrap();
+        ::std::mem::forget(first);
+        let second = alloc.allocate([2u8; 16]).unwrap();
+        assert_eq!(second[15], 2);
+    }
+
+    #[test]
+    fn cache_aligned() {
+        let alloc = CacheAligned::new(HEAP);
+        for _ in 0..8 {
+            let val = alloc.allocate(0u8).unwrap();
+            assert_eq!(&*val as *const u8 as usize % 64, 0);
+            assert_eq!(val.allocated_align(), 64);
+        }
+    }
+
+    #[test]
+    fn backtrace_leaks() {
+        let alloc = Backtrace::new(HEAP);
+        let kept = alloc.allocate(1u32).unwrap();
+        drop(alloc.allocate(2u32).unwrap());
+        assert_eq!(alloc.leaks().len(), 1);
+        drop(kept);
+        assert!(alloc.leaks().is_empty());
+    }
+
+    #[test]
+    fn proxy_in_fallback() {
+        use std::cell::Cell;
+
+        struct Counter(Cell<usize>);
+        impl ProxyLogger for Counter {
+            fn allocate_success(&self, _block: &Block) {
+                self.0.set(self.0.get() + 1);
+            }
+            fn allocate_fail(&self, _err: &Error, _size: usize, _align: usize) {}
+            fn deallocate(&self, _block: &Block) {}
+            fn reallocate_success(&self, _old_block: &Block, _new_block: &Block) {}
+            fn reallocate_fail(&self, _err: &Error, _block: &Block, _req_size: usize) {}
+        }
+
+        let main = Proxy::new(FreeList::new(16, 1).unwrap(), Counter(Cell::new(0)));
+        let alloc = Fallback::new(main, Scoped::new(64).unwrap());
+        let first = alloc.allocate(1u64).unwrap();
+        let second = alloc.allocate(2u64).unwrap();
+        assert!(alloc.main.owns_block(&unsafe { first.as_block() }));
+        assert!(!alloc.main.owns_block(&unsafe { second.as_block() }));
+        assert_eq!(alloc.main.logger.0.get(), 1);
+    }
+
+    #[test]
+    fn wrappers_in_fallback() {
+        let alloc = Fallback::new(CacheAligned::new(Scoped::new(64).unwrap()),
+                                  Backtrace::new(Scoped::new(64).unwrap()));
+        let first = alloc.allocate(1u8).unwrap();
+        let second = alloc.allocate([0u8; 64]).unwrap();
+        assert!(alloc.main.owns_block(&unsafe { first.as_block() }));
+        assert!(alloc.fallback.owns_block(&unsafe { second.as_block() }));
+        drop(second);
+        assert!(alloc.fallback.leaks().is_empty());
+    }
+
+    #[test]
+    fn tracked_census() {
+        let alloc = TrackedAllocator::new(HEAP);
+        let first = alloc.allocate(1u32).unwrap();
+        let second = alloc.allocate([0u64; 4]).unwrap();
+        assert_eq!(alloc.live().count(), 2);
+
+        drop(first);
+        let live: Vec<_> = alloc.live().collect();
+        assert_eq!(live, vec![(&*second as *const _ as *mut u8, 32, 8)]);
+    }
+
+    #[test]
+    fn tracked_deallocate_all() {
+        let alloc = TrackedAllocator::new(FreeList::new(16, 2).unwrap());
+        ::std::mem::forget(alloc.allocate(1u64).unwrap());
+        ::std::mem::forget(alloc.allocate(2u64).unwrap());
+        assert!(alloc.allocate(3u64).is_err());
+
+        unsafe { alloc.deallocate_all().unwrap() };
+        assert_eq!(alloc.live().count(), 0);
+        assert!(alloc.allocate(3u64).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "bench")]
+    fn scratch_aliases() {
+        let alloc = unsafe { ScratchAllocator::new() };
+        let first = alloc.allocate(1u32).unwrap();
+        let second = alloc.allocate(2u32).unwrap();
+        assert_eq!(&*first as *const u32, &*second as *const u32);
+        assert!(alloc.allocate([0u8; SCRATCH_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn deferred_frees() {
+        let alloc = Deferred::new(FreeList::new(16, 2).unwrap());
+        drop(alloc.allocate(1u64).unwrap());
+        drop(alloc.allocate(2u64).unwrap());
+        assert_eq!(alloc.pending_count(), 2);
+        assert!(alloc.allocate(3u64).is_err());
+
+        alloc.flush();
+        assert_eq!(alloc.pending_count(), 0);
+        assert!(alloc.allocate(3u64).is_ok());
+    }
+
+    #[test]
+    fn checked() {
+        let alloc = Checked::new(HEAP);
+        let mut val = alloc.allocate(vec![1u8]).unwrap();
+        val.push(2);
+        drop(val);
+        unsafe {
+            let block = alloc.allocate_raw(8, 8).unwrap();
+            let block = alloc.reallocate_raw(block, 16).ok().unwrap();
+            alloc.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not a power of two")]
+    fn checked_bad_align() {
+        let _ = unsafe { Checked::new(HEAP).allocate_raw(8, 3) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "overflows when aligned")]
+    fn checked_overflow() {
+        let _ = unsafe { Checked::new(HEAP).allocate_raw(usize::max_value(), 8) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "unknown or was already freed")]
+    fn checked_double_free() {
+        let alloc = Checked::new(HEAP);
+        unsafe {
+            let block = alloc.allocate_raw(8, 8).unwrap();
+            let ptr = block.ptr();
+            alloc.deallocate_raw(block);
+            alloc.deallocate_raw(Block::new(ptr, 8, 8));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "unknown or was already freed")]
+    fn checked_unknown_realloc() {
+        let alloc = Checked::new(HEAP);
+        let mut val = 0u64;
+        let _ = unsafe { alloc.reallocate_raw(Block::new(&mut val as *mut _ as *mut u8, 8, 8), 16) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "but was allocated with")]
+    fn checked_mismatched_size() {
+        let alloc = Checked::new(HEAP);
+        unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            let block = Block::new(block.ptr(), 8, 8);
+            alloc.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    fn capped() {
+        let alloc = Capped::new(HEAP, 16);
+        let first = alloc.allocate(1u64).unwrap();
+        let second = alloc.allocate(2u64).unwrap();
+        assert_eq!(alloc.remaining(), Some(0));
+        assert!(alloc.allocate(3u8).is_err());
+        drop(first);
+        assert_eq!(alloc.used(), 8);
+        drop(second);
+        assert_eq!(alloc.used(), 0);
+    }
+
+    #[test]
+    fn mirror() {
+        let alloc = Mirror::new(HEAP, Scoped::new(64).unwrap());
+        unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            ptr::write_bytes(block.ptr(), 0xab, 16);
+            assert!(!alloc.verify(&block));
+            alloc.sync(&block);
+            assert!(alloc.verify(&block));
+            assert!(alloc.shadow(&block).unwrap() != block.ptr());
+
+            // corruption is caught, and can be undone.
+            *block.ptr().offset(3) = 0;
+            assert!(!alloc.verify(&block));
+            alloc.restore(&block);
+            assert!(alloc.verify(&block));
+            assert_eq!(*block.ptr().offset(3), 0xab);
+
+            let block = alloc.reallocate_raw(block, 32).ok().unwrap();
+            assert!(alloc.shadow(&block).is_some());
+            alloc.deallocate_raw(block);
+        }
+        assert_eq!(alloc.report().bytes_outstanding, 0);
+    }
+
+    #[test]
+    fn mirror_realloc_from_empty() {
+        // allocates afresh when asked to reallocate an empty block.
+        struct Growing;
+
+        unsafe impl Allocator for Growing {
+            unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+                HEAP.allocate_raw(size, align)
+            }
+
+            unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+                if block.is_empty() {
+                    match HEAP.allocate_raw(new_size, 8) {
+                        Ok(new_block) => Ok(new_block),
+                        Err(err) => Err((err, block)),
+                    }
+                } else {
+                    HEAP.reallocate_raw(block, new_size)
+                }
+            }
+
+            unsafe fn deallocate_raw(&self, block: Block) {
+                HEAP.deallocate_raw(block)
+            }
+        }
+
+        let alloc = Mirror::new(Growing, Scoped::new(64).unwrap());
+        unsafe {
+            let block = alloc.reallocate_raw(Block::empty(), 16).ok().unwrap();
+            assert!(alloc.shadow(&block).is_some());
+            ptr::write_bytes(block.ptr(), 0xcd, 16);
+            alloc.sync(&block);
+            *block.ptr() = 0;
+            assert!(!alloc.verify(&block));
+            alloc.deallocate_raw(block);
+        }
+        assert_eq!(alloc.report().bytes_outstanding, 0);
+
+        // without room for a shadow, the empty block is given back.
+        let alloc = Mirror::new(Growing, NullAllocator);
+        unsafe {
+            let (_, block) = alloc.reallocate_raw(Block::empty(), 16).err().unwrap();
+            assert!(block.is_empty());
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let alloc = AllocatorBuilder::new(FreeList::new(64, 1).unwrap())
+                        .with_fallback(Scoped::new(256).unwrap())
+                        .capped_at(128)
+                        .cache_aligned()
+                        .build();
+        let first = alloc.allocate([0u8; 64]).unwrap();
+        let second = alloc.allocate([0u8; 64]).unwrap();
+        assert_eq!(&*second as *const _ as usize % DEFAULT_CACHE_LINE_SIZE, 0);
+        assert!(alloc.allocate(0u8).is_err());
+        drop(first);
+        drop(second);
+    }
+
+    #[test]
+    fn owns_ptr() {
+        let alloc = Fallback::new(FreeList::new(16, 1).unwrap(), Scoped::new(64).unwrap());
+        let first = alloc.allocate(1u64).unwrap();
+        let second = alloc.allocate(2u64).unwrap();
+        let (first_ptr, second_ptr) = (&*first as *const u64 as *mut u8, &*second as *const u64 as *mut u8);
+        assert!(alloc.main.owns_ptr(first_ptr));
+        assert!(!alloc.main.owns_ptr(second_ptr));
+        assert!(alloc.fallback.owns_ptr(second_ptr));
+        assert!(alloc.owns_ptr(first_ptr) && alloc.owns_ptr(second_ptr));
+
+        let mut elsewhere = 0u64;
+        assert!(!alloc.owns_ptr(&mut elsewhere as *mut u64 as *mut u8));
+    }
+
+    #[test]
+    fn round_robin() {
+        let pools = vec![FreeList::new(16, 3).unwrap(),
+                         FreeList::new(16, 3).unwrap(),
+                         FreeList::new(16, 3).unwrap()];
+        let alloc = RoundRobin::new(pools);
+        let boxes: Vec<_> = (0..6u64).map(|i| alloc.allocate(i).unwrap()).collect();
+        for pool in alloc.backends() {
+            assert_eq!(pool.free(), 1);
+        }
+
+        drop(boxes);
+        for pool in alloc.backends() {
+            assert_eq!(pool.free(), 3);
+        }
+    }
+
+    #[test]
+    fn sampling_proxy() {
+        use std::cell::Cell;
+
+        struct Counter(Cell<usize>);
+        impl ProxyLogger for Counter {
+            fn allocate_success(&self, block: &Block) {
+                assert_eq!((block.size(), block.align()), (4, 4));
+                self.0.set(self.0.get() + 1);
+            }
+            fn allocate_fail(&self, _err: &Error, _size: usize, _align: usize) {}
+            fn deallocate(&self, _block: &Block) {}
+            fn reallocate_success(&self, _old_block: &Block, _new_block: &Block) {}
+            fn reallocate_fail(&self, _err: &Error, _block: &Block, _req_size: usize) {}
+        }
+
+        let alloc = SamplingProxy::new(HEAP, Counter(Cell::new(0)), 10);
+        let boxes: Vec<_> = (0..100u32).map(|i| alloc.allocate(i).unwrap()).collect();
+        assert_eq!(alloc.logger.0.get(), 100 / alloc.rate());
+        drop(boxes);
     }
 }
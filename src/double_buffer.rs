@@ -0,0 +1,191 @@
+//! A double-buffered allocator for transient, per-frame allocations.
+
+use super::{Allocator, CapacityAware, Error, Block, BlockOwner, HeapAllocator, Report, Reset, HEAP};
+use super::scoped::Scoped;
+
+/// An allocator made of two `Scoped` buffers which take turns serving allocations.
+///
+/// Every call to `swap` marks the end of a frame: the other buffer becomes current,
+/// and is reset before it serves any allocations. Since a buffer is only reset when it is
+/// swapped back in, memory allocated during one frame stays valid through the next one,
+/// and is reclaimed at the start of the frame after that.
+///
+/// `swap` takes `&mut self`, so the borrow checker ensures that no `AllocBox`es or
+/// `Block`s outlive the frame they were allocated in. The two-frame lifetime is only
+/// relevant to raw pointers taken from them.
+pub struct DoubleBuffer<'parent, A: 'parent + Allocator> {
+    buffers: [Scoped<'parent, A>; 2],
+    current: usize,
+}
+
+impl DoubleBuffer<'static, HeapAllocator> {
+    /// Creates a new `DoubleBuffer` with two buffers of `size` bytes each from the heap.
+    pub fn new(size: usize) -> Result<Self, Error> {
+        DoubleBuffer::new_from(HEAP, size)
+    }
+}
+
+impl<'parent, A: Allocator> DoubleBuffer<'parent, A> {
+    /// Creates a new `DoubleBuffer` with two buffers of `size` bytes each
+    /// from the allocator supplied.
+    pub fn new_from(alloc: &'parent A, size: usize) -> Result<Self, Error> {
+        let front = match Scoped::new_from(alloc, size) {
+            Ok(front) => front,
+            Err(err) => return Err(err),
+        };
+
+        match Scoped::new_from(alloc, size) {
+            Ok(back) => Ok(DoubleBuffer {
+                buffers: [front, back],
+                current: 0,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the buffer currently serving allocations.
+    pub fn current(&self) -> &Scoped<'parent, A> {
+        &self.buffers[self.current]
+    }
+
+    /// Registers a callback to run when the memory allocated during the current frame
+    /// is reclaimed: when its buffer is swapped back in, or the whole allocator is reset.
+    /// See `Scoped::on_reset`.
+    pub fn on_reset<F: FnOnce() + Send + 'parent>(&self, f: F) {
+        self.current().on_reset(f)
+    }
+
+    /// Ends the current frame, swapping which buffer serves allocations.
+    ///
+    /// The buffer being swapped in is reset, reclaiming everything allocated
+    /// from it during the frame before last.
+    pub fn swap(&mut self) {
+        self.current ^= 1;
+        unsafe { self.buffers[self.current].reset() }
+    }
+
+    /// Checks that both buffers are consistent.
+    #[cfg(any(test, feature = "invariants"))]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        match self.buffers[0].check_invariants() {
+            Ok(()) => self.buffers[1].check_invariants(),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+unsafe impl<'parent, A: Allocator> Allocator for DoubleBuffer<'parent, A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        self.current().allocate_raw(size, align)
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        if self.buffers[0].owns_block(&block) {
+            self.buffers[0].reallocate_raw(block, new_size)
+        } else {
+            self.buffers[1].reallocate_raw(block, new_size)
+        }
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if self.buffers[0].owns_block(&block) {
+            self.buffers[0].deallocate_raw(block)
+        } else {
+            self.buffers[1].deallocate_raw(block)
+        }
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("DoubleBuffer");
+        report.merge(self.buffers[0].report());
+        report.merge(self.buffers[1].report());
+        report
+    }
+}
+
+impl<'parent, A: Allocator> CapacityAware for DoubleBuffer<'parent, A> {
+    /// The remaining capacity of the current buffer.
+    fn remaining(&self) -> Option<usize> {
+        self.current().remaining()
+    }
+}
+
+impl<'parent, A: Allocator> Reset for DoubleBuffer<'parent, A> {
+    /// Resets both buffers.
+    unsafe fn reset(&self) {
+        self.buffers[0].reset();
+        self.buffers[1].reset();
+    }
+}
+
+impl<'parent, A: Allocator> BlockOwner for DoubleBuffer<'parent, A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.buffers[0].owns_ptr(ptr) || self.buffers[1].owns_ptr(ptr)
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        match self.buffers[0].deallocate_all() {
+            Ok(()) => self.buffers[1].deallocate_all(),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn frames_alternate() {
+        let mut alloc = DoubleBuffer::new(64).unwrap();
+
+        let first = unsafe { alloc.allocate_raw(16, 8).unwrap().ptr() };
+        alloc.swap();
+
+        let second = unsafe { alloc.allocate_raw(16, 8).unwrap() };
+        assert!(second.ptr() != first);
+        assert!(!alloc.buffers[0].owns_block(&second));
+        drop(second);
+        alloc.swap();
+
+        // the first buffer was reset when it was swapped back in.
+        let third = unsafe { alloc.allocate_raw(16, 8).unwrap().ptr() };
+        assert_eq!(third, first);
+    }
+
+    #[test]
+    fn previous_frame_survives() {
+        let mut alloc = DoubleBuffer::new(64).unwrap();
+        let ptr = {
+            let mut val = alloc.allocate(0u32).unwrap();
+            *val = 42;
+            let ptr = &mut *val as *mut u32;
+            ::std::mem::forget(val);
+            ptr
+        };
+
+        alloc.swap();
+        let _ = alloc.allocate([1u32; 16]).unwrap();
+        assert_eq!(unsafe { *ptr }, 42);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn on_reset() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut alloc = DoubleBuffer::new(64).unwrap();
+        let resets = Arc::new(AtomicUsize::new(0));
+        let counter = resets.clone();
+        alloc.on_reset(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        // the first frame's memory survives the next one.
+        alloc.swap();
+        assert_eq!(resets.load(Ordering::SeqCst), 0);
+        alloc.swap();
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+    }
+}
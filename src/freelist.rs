@@ -1,112 +1,468 @@
 //! A Free List allocator.
 
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
 use std::mem;
 use std::ptr;
 
-use super::{Allocator, Error, Block, HeapAllocator, HEAP};
+use super::{AllocBox, Allocator, CapacityAware, Error, Block, BlockOwner, HeapAllocator, Report, HEAP};
 
 /// A `FreeList` allocator manages a list of free memory blocks of uniform size.
 /// Whenever a block is requested, it returns the first free block.
 pub struct FreeList<'a, A: 'a + Allocator> {
     alloc: &'a A,
     block_size: usize,
+    block_align: usize,
     free_list: Cell<*mut u8>,
+    // the addresses of all blocks owned, sorted.
+    blocks: RefCell<Vec<usize>>,
+    num_blocks: Cell<usize>,
+    num_free: Cell<usize>,
+    allocations: Cell<usize>,
+    failures: Cell<usize>,
+    // the number of allocations of each requested size, if tracked.
+    sizes: Option<RefCell<BTreeMap<usize, usize>>>,
 }
 
 impl FreeList<'static, HeapAllocator> {
     /// Creates a new `FreeList` backed by the heap. `block_size` must be greater
-    /// than or equal to the size of a pointer.
+    /// than or equal to the size of a pointer, and a multiple of its alignment.
     pub fn new(block_size: usize, num_blocks: usize) -> Result<Self, Error> {
         FreeList::new_from(HEAP, block_size, num_blocks)
     }
+
+    /// Creates a new `FreeList` backed by the heap, whose blocks are aligned to `block_align`.
+    /// See `new_from_aligned` for the requirements.
+    pub fn new_aligned(block_size: usize, block_align: usize, num_blocks: usize) -> Result<Self, Error> {
+        FreeList::new_from_aligned(HEAP, block_size, block_align, num_blocks)
+    }
 }
 impl<'a, A: 'a + Allocator> FreeList<'a, A> {
     /// Creates a new `FreeList` backed by another allocator. `block_size` must be greater
-    /// than or equal to the size of a pointer.
+    /// than or equal to the size of a pointer, and a multiple of its alignment.
     pub fn new_from(alloc: &'a A,
                     block_size: usize,
                     num_blocks: usize)
                     -> Result<Self, Error> {
+        FreeList::new_from_aligned(alloc, block_size, mem::align_of::<*mut u8>(), num_blocks)
+    }
+
+    /// Creates a new `FreeList` backed by another allocator, whose blocks are aligned
+    /// to `block_align`.
+    ///
+    /// `block_align` must be a power of two no smaller than the alignment of a pointer.
+    /// `block_size` must be greater than or equal to the size of a pointer,
+    /// and a multiple of `block_align`.
+    pub fn new_from_aligned(alloc: &'a A,
+                            block_size: usize,
+                            block_align: usize,
+                            num_blocks: usize)
+                            -> Result<Self, Error> {
         if block_size < mem::size_of::<*mut u8>() {
             return Err(Error::AllocatorSpecific("Block size too small.".into()));
         }
+        if !block_align.is_power_of_two() || block_align < mem::align_of::<*mut u8>() {
+            return Err(Error::UnsupportedAlignment);
+        }
+        if block_size % block_align != 0 {
+            return Err(Error::AllocatorSpecific("Block size not a multiple of block alignment.".into()));
+        }
 
-        let mut free_list = ptr::null_mut();
+        let list = FreeList {
+            alloc: alloc,
+            block_size: block_size,
+            block_align: block_align,
+            free_list: Cell::new(ptr::null_mut()),
+            blocks: RefCell::new(Vec::with_capacity(num_blocks)),
+            num_blocks: Cell::new(0),
+            num_free: Cell::new(0),
+            allocations: Cell::new(0),
+            failures: Cell::new(0),
+            sizes: None,
+        };
 
-        // allocate each block with maximal alignment.
         for _ in 0..num_blocks {
+            // destructor cleans up after us.
+            if let Err(err) = list.add_block() {
+                return Err(err);
+            }
+        }
 
-            match unsafe { alloc.allocate_raw(block_size, mem::align_of::<*mut u8>()) } {
-                Ok(block) => {
-                    let ptr: *mut *mut u8 = block.ptr() as *mut *mut u8;
-                    unsafe { *ptr = free_list }
-                    free_list = block.ptr();
-                }
-                Err(err) => {
-                    // destructor cleans up after us.
-                    drop(FreeList {
-                        alloc: alloc,
-                        block_size: block_size,
-                        free_list: Cell::new(free_list),
-                    });
-
-                    return Err(err);
-                }
+        Ok(list)
+    }
+
+    /// Get the size of the blocks in this list.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Get the alignment of the blocks in this list.
+    pub fn block_align(&self) -> usize {
+        self.block_align
+    }
+
+    /// Get the number of blocks this list owns.
+    pub fn capacity(&self) -> usize {
+        self.num_blocks.get()
+    }
+
+    /// Get the number of blocks which are free to allocate.
+    pub fn free(&self) -> usize {
+        self.num_free.get()
+    }
+
+    /// Enables tracking the sizes requested from this list, for tuning its block size.
+    /// This adds the cost of a map update to every allocation.
+    pub fn with_size_histogram(mut self) -> Self {
+        self.sizes = Some(RefCell::new(BTreeMap::new()));
+        self
+    }
+
+    /// Relinks the free blocks so that they are handed out in ascending address order.
+    ///
+    /// Blocks are otherwise handed out in the reverse of the order they were added in,
+    /// so a burst of allocations walks memory backwards. Sequential access to blocks
+    /// allocated in a burst is friendlier to the cache and prefetcher in ascending order.
+    /// This only changes which blocks come out first.
+    pub fn with_ascending_order(self) -> Self {
+        let mut free = Vec::with_capacity(self.num_free.get());
+        let mut next = self.free_list.get();
+        while !next.is_null() {
+            free.push(next);
+            next = unsafe { *(next as *mut *mut u8) };
+        }
+        free.sort();
+
+        self.free_list.set(ptr::null_mut());
+        self.num_free.set(0);
+        for &ptr in free.iter().rev() {
+            unsafe { self.push(ptr) };
+        }
+        self
+    }
+
+    /// The number of allocations made of each size, in increasing order of size.
+    /// Returns `None` unless enabled with `with_size_histogram`.
+    pub fn size_histogram(&self) -> Option<Vec<(usize, usize)>> {
+        match self.sizes {
+            Some(ref sizes) => Some(sizes.borrow().iter().map(|(&size, &count)| (size, count)).collect()),
+            None => None,
+        }
+    }
+
+    /// The average number of bytes per allocation left unused by the requested size.
+    /// Returns `None` unless enabled with `with_size_histogram`,
+    /// or if nothing has been allocated yet.
+    pub fn average_waste(&self) -> Option<f64> {
+        let sizes = match self.sizes {
+            Some(ref sizes) => sizes.borrow(),
+            None => return None,
+        };
+
+        let count: usize = sizes.values().sum();
+        if count == 0 {
+            return None;
+        }
+
+        let waste: usize = sizes.iter().map(|(&size, &count)| (self.block_size - size) * count).sum();
+        Some(waste as f64 / count as f64)
+    }
+
+    /// Attempts to add `additional` blocks to the list, stopping at the first
+    /// one the backing allocator can't provide.
+    ///
+    /// Returns the number of blocks added, or the allocator's error if it
+    /// couldn't provide any of them.
+    pub fn try_grow(&self, additional: usize) -> Result<usize, Error> {
+        for added in 0..additional {
+            if let Err(err) = self.add_block() {
+                return if added == 0 { Err(err) } else { Ok(added) };
             }
         }
 
-        Ok(FreeList {
-            alloc: alloc,
-            block_size: block_size,
-            free_list: Cell::new(free_list),
-        })
+        Ok(additional)
+    }
+
+    /// Links a block into this list, even if it was allocated by another list.
+    /// From then on, this list owns the block and frees it when dropped.
+    ///
+    /// Returns the block along with an error if it's larger than this list's blocks
+    /// or not aligned to their alignment.
+    ///
+    /// # Safety
+    /// The block must be no longer in use, and its memory must be exactly what this list
+    /// would have allocated for one of its blocks: `block_size` bytes aligned to
+    /// `block_align`, allocated from the same backing allocator. This holds for blocks
+    /// taken from another list with the same block size and alignment and the same
+    /// backing allocator. That list must not free the block itself, whether by
+    /// `deallocate_raw` or `deallocate_all`.
+    pub unsafe fn adopt<'b>(&self, block: Block<'b>) -> Result<(), (Error, Block<'b>)> {
+        if block.is_empty() || block.size() > self.block_size {
+            return Err((Error::AllocatorSpecific("Block doesn't fit in this list.".into()), block));
+        } else if block.ptr() as usize % self.block_align != 0 {
+            return Err((Error::UnsupportedAlignment, block));
+        }
+
+        let addr = block.ptr() as usize;
+        {
+            let mut blocks = self.blocks.borrow_mut();
+            if let Err(idx) = blocks.binary_search(&addr) {
+                blocks.insert(idx, addr);
+                self.num_blocks.set(self.num_blocks.get() + 1);
+            }
+        }
+
+        self.push(block.ptr());
+        Ok(())
+    }
+
+    /// Checks that the list is consistent: that it isn't cyclic,
+    /// and that it holds as many blocks as it should.
+    #[cfg(any(test, feature = "invariants"))]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut len = 0;
+        let mut next = self.free_list.get();
+        while !next.is_null() {
+            len += 1;
+            if len > self.num_blocks.get() {
+                return Err(format!("Free list is longer than the {} blocks owned; it may be cyclic.",
+                                   self.num_blocks.get()));
+            }
+            if next as usize % self.block_align != 0 {
+                return Err(format!("Free block {:?} is misaligned.", next));
+            }
+            next = unsafe { *(next as *mut *mut u8) };
+        }
+
+        if len != self.num_free.get() {
+            return Err(format!("Free list holds {} blocks, but {} were counted.",
+                               len,
+                               self.num_free.get()));
+        }
+        Ok(())
+    }
+
+    // allocate a new block from the backing allocator and push it onto the list.
+    fn add_block(&self) -> Result<(), Error> {
+        match unsafe { self.alloc.allocate_raw(self.block_size, self.block_align) } {
+            Ok(block) => {
+                let addr = block.ptr() as usize;
+                let mut blocks = self.blocks.borrow_mut();
+                let idx = blocks.binary_search(&addr).unwrap_or_else(|idx| idx);
+                blocks.insert(idx, addr);
+
+                unsafe { self.push(block.ptr()) };
+                self.num_blocks.set(self.num_blocks.get() + 1);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // panics if the block is already in the free list.
+    unsafe fn assert_not_free(&self, ptr: *mut u8) {
+        let mut next = self.free_list.get();
+        while !next.is_null() {
+            if next == ptr {
+                panic!("Block {:?} was freed twice.", ptr);
+            }
+            next = *(next as *mut *mut u8);
+        }
+    }
+
+    // the number of blocks an allocation of `size` bytes spans.
+    fn pieces(&self, size: usize) -> usize {
+        if size <= self.block_size {
+            1
+        } else {
+            (size + self.block_size - 1) / self.block_size
+        }
+    }
+
+    // unlinks the blocks `from..to` places after `ptr` from the free list,
+    // if they are all owned and free. Otherwise, leaves the list untouched.
+    unsafe fn take_adjacent(&self, ptr: *mut u8, from: usize, to: usize) -> bool {
+        let wanted: Vec<usize> = (from..to).map(|i| ptr as usize + i * self.block_size).collect();
+        {
+            let blocks = self.blocks.borrow();
+            if wanted.iter().any(|addr| blocks.binary_search(addr).is_err()) {
+                return false;
+            }
+        }
+
+        let mut found = 0;
+        let mut next = self.free_list.get();
+        while !next.is_null() && found < wanted.len() {
+            if wanted.contains(&(next as usize)) {
+                found += 1;
+            }
+            next = *(next as *mut *mut u8);
+        }
+        if found < wanted.len() {
+            return false;
+        }
+
+        // relink the list around the blocks taken.
+        let mut link = self.free_list.as_ptr();
+        while !(*link).is_null() {
+            let cur = *link;
+            if wanted.contains(&(cur as usize)) {
+                *link = *(cur as *mut *mut u8);
+            } else {
+                link = cur as *mut *mut u8;
+            }
+        }
+        self.num_free.set(self.num_free.get() - wanted.len());
+        true
+    }
+
+    unsafe fn push(&self, ptr: *mut u8) {
+        *(ptr as *mut *mut u8) = self.free_list.get();
+        self.free_list.set(ptr);
+        self.num_free.set(self.num_free.get() + 1);
     }
 }
 
 unsafe impl<'a, A: 'a + Allocator> Allocator for FreeList<'a, A> {
     unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
         if size == 0 {
             return Ok(Block::empty());
         } else if size > self.block_size {
-            return Err(Error::OutOfMemory);
+            return Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            });
         }
 
-        if align > mem::align_of::<*mut u8>() {
-            return Err(Error::UnsupportedAlignment);
+        if align > self.block_align {
+            return Err(Error::AlignmentTooLarge {
+                requested: align,
+                max: self.block_align,
+            });
         }
 
         let free_list = self.free_list.get();
         if !free_list.is_null() {
             let next_block = *(free_list as *mut *mut u8);
             self.free_list.set(next_block);
+            self.num_free.set(self.num_free.get() - 1);
+            self.allocations.set(self.allocations.get() + 1);
+            if let Some(ref sizes) = self.sizes {
+                *sizes.borrow_mut().entry(size).or_insert(0) += 1;
+            }
 
             Ok(Block::new(free_list, size, align))
         } else {
-            Err(Error::OutOfMemory)
+            self.failures.set(self.failures.get() + 1);
+            Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            })
         }
     }
 
+    /// Blocks which grow beyond the block size take over the blocks directly after them,
+    /// as long as those are all free. Finding them means walking the free list, so growing
+    /// beyond the block size is O(n) in the number of free blocks.
     unsafe fn reallocate_raw<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
         if new_size == 0 {
-            Ok(Block::empty())
+            return Ok(Block::empty());
         } else if block.is_empty() {
-            Err((Error::UnsupportedAlignment, block))
-        } else if new_size <= self.block_size {
+            return Err((Error::UnsupportedAlignment, block));
+        }
+
+        let (old_pieces, new_pieces) = (self.pieces(block.size()), self.pieces(new_size));
+        if new_pieces <= old_pieces {
+            // give back the blocks no longer spanned.
+            for i in new_pieces..old_pieces {
+                self.push(block.ptr().offset((i * self.block_size) as isize));
+            }
+            Ok(Block::new(block.ptr(), new_size, block.align()))
+        } else if self.take_adjacent(block.ptr(), old_pieces, new_pieces) {
             Ok(Block::new(block.ptr(), new_size, block.align()))
         } else {
-            Err((Error::OutOfMemory, block))
+            let align = block.align();
+            Err((Error::OutOfMemory {
+                requested: new_size,
+                align: align,
+            }, block))
+        }
+    }
+
+    /// The block returned spans whole blocks of the list, so a block grown within
+    /// the block size comes back the full block size.
+    unsafe fn reallocate_to_usable<'b>(&'b self,
+                                       block: Block<'b>,
+                                       new_size: usize)
+                                       -> Result<Block<'b>, (Error, Block<'b>)> {
+        match self.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                if new_block.is_empty() {
+                    Ok(new_block)
+                } else {
+                    let usable = self.pieces(new_size) * self.block_size;
+                    Ok(Block::new(new_block.ptr(), usable, new_block.align()))
+                }
+            }
+            Err(err) => Err(err),
         }
     }
 
+    /// Blocks can grow in place up to the size of the blocks they span.
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        !block.is_empty() && new_size <= self.pieces(block.size()) * self.block_size
+    }
+
+    /// Blocks which were grown are split back into the blocks they span.
+    ///
+    /// In debug builds, this panics if the block is already free.
+    /// Checking this means walking the whole free list on every deallocation.
     unsafe fn deallocate_raw(&self, block: Block) {
         if !block.is_empty() {
-            let first = self.free_list.get();
-            let ptr = block.ptr();
-            *(ptr as *mut *mut u8) = first;
-            self.free_list.set(ptr);
+            for i in 0..self.pieces(block.size()) {
+                let ptr = block.ptr().offset((i * self.block_size) as isize);
+                if cfg!(debug_assertions) {
+                    self.assert_not_free(ptr);
+                }
+                self.push(ptr);
+            }
         }
     }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("FreeList");
+        report.bytes_outstanding = (self.num_blocks.get() - self.num_free.get()) * self.block_size;
+        report.allocations = self.allocations.get();
+        report.failures = self.failures.get();
+        report
+    }
+}
+
+impl<'a, A: 'a + Allocator> CapacityAware for FreeList<'a, A> {
+    fn remaining(&self) -> Option<usize> {
+        Some(self.num_free.get() * self.block_size)
+    }
+}
+
+impl<'a, A: 'a + Allocator> BlockOwner for FreeList<'a, A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        self.blocks.borrow().binary_search(&(ptr as usize)).is_ok()
+    }
+
+    /// Relinks every block this list owns into the free list.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.free_list.set(ptr::null_mut());
+        self.num_free.set(0);
+        for &addr in self.blocks.borrow().iter() {
+            self.push(addr as *mut u8);
+        }
+        Ok(())
+    }
 }
 
 impl<'a, A: 'a + Allocator> Drop for FreeList<'a, A> {
@@ -118,7 +474,7 @@ impl<'a, A: 'a + Allocator> Drop for FreeList<'a, A> {
                 let next = *(free_list as *mut *mut u8);
                 self.alloc.deallocate_raw(Block::new(free_list,
                                                      self.block_size,
-                                                     mem::align_of::<*mut u8>()));
+                                                     self.block_align));
                 free_list = next;
             }
         }
@@ -127,6 +483,71 @@ impl<'a, A: 'a + Allocator> Drop for FreeList<'a, A> {
 
 unsafe impl<'a, A: 'a + Allocator + Sync> Send for FreeList<'a, A> {}
 
+/// A `FreeList` sized for values of type `T`, which are allocated and freed by value.
+///
+/// The block size is worked out from `T`: its size, rounded up to hold at least a pointer,
+/// and aligned to the larger of its alignment and a pointer's. Values are freed back into
+/// the list when the boxes holding them are dropped.
+///
+/// # Examples
+/// ```rust
+/// use allocators::TypedFreeList;
+///
+/// let list = TypedFreeList::<[u32; 4], _>::new(8).unwrap();
+/// let val = list.alloc([1, 2, 3, 4]).ok().unwrap();
+/// assert_eq!(list.free(), 7);
+/// drop(val);
+/// assert_eq!(list.free(), 8);
+/// ```
+pub struct TypedFreeList<'a, T, A: 'a + Allocator> {
+    list: FreeList<'a, A>,
+    _marker: PhantomData<T>,
+}
+
+impl<T> TypedFreeList<'static, T, HeapAllocator> {
+    /// Creates a new `TypedFreeList` backed by the heap, with room for `num_blocks` values.
+    pub fn new(num_blocks: usize) -> Result<Self, Error> {
+        TypedFreeList::new_from(HEAP, num_blocks)
+    }
+}
+
+impl<'a, T, A: 'a + Allocator> TypedFreeList<'a, T, A> {
+    /// Creates a new `TypedFreeList` backed by another allocator, with room for
+    /// `num_blocks` values.
+    pub fn new_from(alloc: &'a A, num_blocks: usize) -> Result<Self, Error> {
+        let align = cmp::max(mem::align_of::<T>(), mem::align_of::<*mut u8>());
+        let size = cmp::max(mem::size_of::<T>(), mem::size_of::<*mut u8>());
+        let block_size = (size + align - 1) & !(align - 1);
+        match FreeList::new_from_aligned(alloc, block_size, align, num_blocks) {
+            Ok(list) => Ok(TypedFreeList {
+                list: list,
+                _marker: PhantomData,
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Moves a value into a free block. On failure, the value is given back along with the error.
+    pub fn alloc(&self, val: T) -> Result<AllocBox<T, FreeList<'a, A>>, (Error, T)> {
+        self.list.allocate(val)
+    }
+
+    /// Get the number of values this list has room for.
+    pub fn capacity(&self) -> usize {
+        self.list.capacity()
+    }
+
+    /// Get the number of values which can be allocated before the list runs out.
+    pub fn free(&self) -> usize {
+        self.list.free()
+    }
+
+    /// Get the underlying `FreeList`.
+    pub fn as_free_list(&self) -> &FreeList<'a, A> {
+        &self.list
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::*;
@@ -142,4 +563,224 @@ mod tests {
         drop(blocks);
         assert!(alloc.allocate([0u8; 1024]).is_ok());
     }
+
+    #[test]
+    fn exhausted_invariants() {
+        let alloc = FreeList::new(1024, 64).ok().unwrap();
+        let mut blocks = Vec::new();
+        for _ in 0..64 {
+            blocks.push(alloc.allocate([0u8; 1024]).ok().unwrap());
+        }
+        alloc.check_invariants().unwrap();
+        assert!(alloc.allocate([0u8; 1024]).is_err());
+        alloc.check_invariants().unwrap();
+        drop(blocks);
+        alloc.check_invariants().unwrap();
+        let _ = alloc.allocate([0u8; 1024]).ok().unwrap();
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn report() {
+        let alloc = FreeList::new(16, 2).ok().unwrap();
+        let _a = alloc.allocate(0u64).ok().unwrap();
+        let report = alloc.report();
+        assert_eq!(report.bytes_outstanding, 16);
+        assert_eq!(report.allocations, 1);
+        assert_eq!(report.failures, 0);
+    }
+
+    #[test]
+    fn partial_growth() {
+        // room for three blocks in total.
+        let scoped = Scoped::new(56).unwrap();
+        let alloc = FreeList::new_from(&scoped, 16, 1).ok().unwrap();
+        assert_eq!(alloc.try_grow(4), Ok(2));
+        assert_eq!(alloc.try_grow(1),
+                   Err(Error::OutOfMemory {
+                       requested: 16,
+                       align: alloc.block_align,
+                   }));
+
+        let blocks: Vec<_> = (0..3).map(|_| alloc.allocate([0u8; 16]).ok().unwrap()).collect();
+        assert!(alloc.allocate([0u8; 16]).is_err());
+        drop(blocks);
+    }
+
+    #[test]
+    fn aligned_blocks() {
+        let alloc = FreeList::new_aligned(64, 32, 4).ok().unwrap();
+        assert_eq!((alloc.block_size(), alloc.block_align()), (64, 32));
+        let block = unsafe { alloc.allocate_raw(64, 32).unwrap() };
+        assert_eq!(block.ptr() as usize % 32, 0);
+        unsafe { alloc.deallocate_raw(block) };
+
+        assert!(FreeList::new_aligned(48, 32, 1).is_err());
+        assert!(FreeList::new_aligned(64, 3, 1).is_err());
+    }
+
+    #[test]
+    fn ascending_order() {
+        let alloc = FreeList::new(16, 8).unwrap().with_ascending_order();
+        assert_eq!(alloc.free(), 8);
+        let blocks: Vec<_> = (0..8).map(|_| unsafe { alloc.allocate_raw(16, 8).unwrap() }).collect();
+        for pair in blocks.windows(2) {
+            assert!(pair[0].ptr() < pair[1].ptr());
+        }
+        for block in blocks {
+            unsafe { alloc.deallocate_raw(block) };
+        }
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn alignment_too_large() {
+        let alloc = FreeList::new_aligned(64, 16, 1).ok().unwrap();
+        let err = match unsafe { alloc.allocate_raw(8, 32) } {
+            Ok(_) => panic!("Allocated with too large an alignment."),
+            Err(err) => err,
+        };
+        assert_eq!(err,
+                   Error::AlignmentTooLarge {
+                       requested: 32,
+                       max: 16,
+                   });
+        assert_eq!(err.to_string(), "Requested alignment of 32 exceeds the maximum of 16.");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "freed twice")]
+    fn double_free() {
+        let alloc = FreeList::new(16, 2).ok().unwrap();
+        unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            let copy = Block::new(block.ptr(), block.size(), block.align());
+            alloc.deallocate_raw(block);
+            alloc.deallocate_raw(copy);
+        }
+    }
+
+    #[test]
+    fn adopt_from_sibling() {
+        let first = FreeList::new(16, 1).ok().unwrap();
+        let second = FreeList::new(16, 1).ok().unwrap();
+
+        unsafe {
+            let block = first.allocate_raw(16, 8).unwrap();
+            second.adopt(block).ok().unwrap();
+        }
+        assert_eq!((first.free(), second.free()), (0, 2));
+
+        let a = second.allocate(0u64).ok().unwrap();
+        let b = second.allocate(0u64).ok().unwrap();
+        assert!(second.owns_block(&unsafe { a.as_block() }));
+        assert!(second.owns_block(&unsafe { b.as_block() }));
+        second.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn deallocate_all() {
+        let alloc = FreeList::new(16, 4).ok().unwrap();
+        for _ in 0..4 {
+            ::std::mem::forget(alloc.allocate(0u64).ok().unwrap());
+        }
+        assert_eq!(alloc.free(), 0);
+
+        unsafe { alloc.deallocate_all().unwrap() };
+        assert_eq!(alloc.free(), 4);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn size_histogram() {
+        let alloc = FreeList::new(32, 8).ok().unwrap().with_size_histogram();
+        let small: Vec<_> = (0..3).map(|_| alloc.allocate([0u8; 8]).ok().unwrap()).collect();
+        let large = alloc.allocate([0u8; 32]).ok().unwrap();
+        drop((small, large));
+
+        assert_eq!(alloc.size_histogram(), Some(vec![(8, 3), (32, 1)]));
+        assert_eq!(alloc.average_waste(), Some(18.0));
+        assert_eq!(FreeList::new(32, 1).ok().unwrap().size_histogram(), None);
+    }
+
+    #[test]
+    fn grow_into_adjacent() {
+        // blocks drawn from a scoped allocator lie back to back.
+        let scoped = Scoped::new(64).unwrap();
+        let alloc = FreeList::new_from(&scoped, 16, 4).ok().unwrap().with_ascending_order();
+        unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            let ptr = block.ptr();
+            let grown = alloc.reallocate_raw(block, 40).ok().unwrap();
+            assert_eq!((grown.ptr(), grown.size()), (ptr, 40));
+            assert_eq!(alloc.free(), 1);
+            assert!(alloc.can_grow_in_place(&grown, 48));
+            alloc.check_invariants().unwrap();
+
+            // the last block has nothing after it to grow into.
+            let last = alloc.allocate_raw(16, 8).unwrap();
+            assert_eq!(last.ptr(), ptr.offset(48));
+            let last = match alloc.reallocate_raw(last, 32) {
+                Ok(_) => panic!("Grew past the end of the list."),
+                Err((_, last)) => last,
+            };
+
+            let shrunk = alloc.reallocate_raw(grown, 16).ok().unwrap();
+            assert_eq!(alloc.free(), 2);
+            alloc.deallocate_raw(shrunk);
+            alloc.deallocate_raw(last);
+        }
+        assert_eq!(alloc.free(), 4);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn typed_free_list() {
+        struct Particle {
+            pos: [f32; 3],
+            alive: bool,
+        }
+
+        let list = TypedFreeList::<Particle, _>::new(2).ok().unwrap();
+        assert_eq!(list.as_free_list().block_size(), 16);
+        let first = list.alloc(Particle { pos: [0.0; 3], alive: true }).ok().unwrap();
+        let ptr = &*first as *const Particle;
+        let _second = list.alloc(Particle { pos: [1.0; 3], alive: true }).ok().unwrap();
+        assert!(list.alloc(Particle { pos: [2.0; 3], alive: false }).is_err());
+
+        // the slot freed by the first is handed out next.
+        drop(first);
+        let third = list.alloc(Particle { pos: [3.0; 3], alive: false }).ok().unwrap();
+        assert_eq!(&*third as *const Particle, ptr);
+        assert_eq!((third.pos[0], third.alive), (3.0, false));
+        assert_eq!(list.free(), 0);
+    }
+
+    #[test]
+    fn reallocate_to_usable() {
+        let alloc = FreeList::new(64, 2).ok().unwrap();
+        unsafe {
+            let block = alloc.allocate_raw(8, 8).unwrap();
+            let block = alloc.reallocate_to_usable(block, 20).ok().unwrap();
+            assert_eq!(block.size(), 64);
+
+            // the usable size can be handed back as is.
+            let block = alloc.reallocate_raw(block, 64).ok().unwrap();
+            alloc.deallocate_raw(block);
+        }
+        assert_eq!(alloc.free(), 2);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn can_grow_in_place() {
+        let alloc = FreeList::new(32, 1).ok().unwrap();
+        unsafe {
+            let block = alloc.allocate_raw(8, 8).unwrap();
+            assert!(alloc.can_grow_in_place(&block, 32));
+            assert!(!alloc.can_grow_in_place(&block, 33));
+            alloc.deallocate_raw(block);
+        }
+    }
 }
@@ -0,0 +1,123 @@
+//! Interning of values in a custom allocator.
+
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use super::{Allocator, AllocBox, Error};
+
+// a pointer to an interned value, hashed and compared by the value itself.
+struct Interned<T>(*const T);
+
+impl<T: Hash> Hash for Interned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        unsafe { (*self.0).hash(state) }
+    }
+}
+
+impl<T: Eq> PartialEq for Interned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { *self.0 == *other.0 }
+    }
+}
+
+impl<T: Eq> Eq for Interned<T> {}
+
+impl<T> Borrow<T> for Interned<T> {
+    fn borrow(&self) -> &T {
+        unsafe { &*self.0 }
+    }
+}
+
+/// Allocates each distinct value only once, handing out shared references to
+/// the same allocation for equal values. This is useful for interning strings or
+/// symbols in an arena.
+///
+/// All values are dropped and freed when the interner is dropped.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Interner, Scoped};
+///
+/// let alloc = Scoped::new(1024).unwrap();
+/// let symbols = Interner::new(&alloc);
+/// let a = symbols.intern("foo".to_string()).ok().unwrap();
+/// let b = symbols.intern("foo".to_string()).ok().unwrap();
+/// assert!(a as *const String == b as *const String);
+/// ```
+pub struct Interner<'a, T: 'a + Hash + Eq, A: 'a + Allocator> {
+    allocator: &'a A,
+    index: RefCell<HashSet<Interned<T>>>,
+    values: RefCell<Vec<AllocBox<'a, T, A>>>,
+}
+
+impl<'a, T: Hash + Eq, A: Allocator> Interner<'a, T, A> {
+    /// Creates a new, empty interner which allocates from `alloc`.
+    pub fn new(alloc: &'a A) -> Self {
+        Interner {
+            allocator: alloc,
+            index: RefCell::new(HashSet::new()),
+            values: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Gets a reference to the interned value equal to `value`, allocating it
+    /// if there is none yet.
+    ///
+    /// If the allocator fails, the value is given back.
+    pub fn intern(&self, value: T) -> Result<&T, (Error, T)> {
+        if let Some(interned) = self.index.borrow().get(&value) {
+            return Ok(unsafe { &*interned.0 });
+        }
+
+        match self.allocator.allocate(value) {
+            Ok(boxed) => {
+                // the value never moves, even as the box is moved around.
+                let ptr = &*boxed as *const T;
+                self.index.borrow_mut().insert(Interned(ptr));
+                self.values.borrow_mut().push(boxed);
+                Ok(unsafe { &*ptr })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The number of distinct values interned.
+    pub fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+
+    /// Whether nothing has been interned yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn deduplicates() {
+        let alloc = Scoped::new(1024).unwrap();
+        let strings = Interner::new(&alloc);
+
+        let first = strings.intern("hello".to_string()).ok().unwrap() as *const String;
+        let other = strings.intern("world".to_string()).ok().unwrap() as *const String;
+        let second = strings.intern("hello".to_string()).ok().unwrap() as *const String;
+
+        assert_eq!(first, second);
+        assert!(first != other);
+        assert_eq!(strings.len(), 2);
+    }
+
+    #[test]
+    fn exhausted_allocator() {
+        let alloc = Scoped::new(8).unwrap();
+        let numbers = Interner::new(&alloc);
+        assert_eq!(*numbers.intern(1u64).ok().unwrap(), 1);
+        assert_eq!(*numbers.intern(1u64).ok().unwrap(), 1);
+        assert_eq!(numbers.intern(2u64).err().unwrap().1, 2);
+    }
+}
@@ -39,6 +39,7 @@
 
 #![feature(
     alloc,
+    allocator_api,
     coerce_unsized,
     heap_api,
     placement_new_protocol,
@@ -48,24 +49,56 @@
     unsize,
 )]
 
+use std::alloc::Layout;
+use std::cmp;
 use std::error::Error as StdError;
 use std::fmt;
-use std::marker::PhantomData;
-use std::ptr::Unique;
+use std::marker::{PhantomData, Unsize};
+use std::mem;
+use std::ops::InPlace;
+use std::ops::Place as StdPlace;
+use std::pin::Pin;
+use std::ptr::{self, NonNull, Unique};
 
 use alloc::heap;
 
 extern crate alloc;
+#[cfg(feature = "os")]
+extern crate libc;
 
+pub mod adapter;
+pub mod arena;
 mod boxed;
+pub mod buckets;
 pub mod composable;
+pub mod double_buffer;
 pub mod freelist;
+pub mod interner;
+#[cfg(feature = "os")]
+pub mod mmap;
+#[cfg(feature = "os")]
+mod os;
+pub mod pool;
+pub mod rc;
+#[cfg(feature = "replay")]
+pub mod replay;
 pub mod scoped;
+pub mod vec;
 
-pub use boxed::{AllocBox, Place};
+pub use adapter::StdAllocatorAdapter;
+pub use arena::{ArenaIndex, TypedArena};
+pub use boxed::{AllocBox, Hooked, OwnedBox, Place};
+pub use buckets::Buckets;
 pub use composable::*;
-pub use freelist::FreeList;
-pub use scoped::Scoped;
+pub use double_buffer::DoubleBuffer;
+pub use freelist::{FreeList, TypedFreeList};
+pub use interner::Interner;
+#[cfg(feature = "os")]
+pub use mmap::MmapAllocator;
+pub use pool::{ObjectPool, Pooled, ThreadLocalPool};
+pub use rc::AllocRc;
+pub use scoped::{AtomicInlineScoped, Epoch, InlineScoped, LazyZeroed, ScopeStack, Scoped};
+pub use vec::AllocVec;
 
 /// A custom memory allocator.
 pub unsafe trait Allocator {
@@ -92,6 +125,115 @@ pub unsafe trait Allocator {
         }
     }
 
+    /// Attempts to allocate the value supplied, returning a handle to the block it
+    /// occupies along with the box.
+    ///
+    /// The block is only a view of the box's memory, e.g. for registering it with
+    /// external tracking structures. The box still owns the memory: the block must
+    /// never be passed to `deallocate_raw` or `reallocate_raw`, and must not be used
+    /// after the box is dropped.
+    fn allocate_with_block<'a, T: 'a>(&'a self, val: T) -> Result<(AllocBox<'a, T, Self>, Block<'a>), (Error, T)>
+    where Self: Sized
+    {
+        match self.allocate(val) {
+            Ok(boxed) => {
+                let block = if boxed.allocated_size() == 0 {
+                    Block::empty()
+                } else {
+                    Block::new(&*boxed as *const T as *mut u8,
+                               boxed.allocated_size(),
+                               boxed.allocated_align())
+                };
+                Ok((boxed, block))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Attempts to allocate the value supplied, dropping it on failure.
+    ///
+    /// This is a convenience for when the value isn't worth getting back;
+    /// use `allocate` otherwise.
+    #[inline]
+    fn try_allocate<T>(&self, val: T) -> Option<AllocBox<T, Self>>
+    where Self: Sized
+    {
+        match self.allocate(val) {
+            Ok(boxed) => Some(boxed),
+            Err(_) => None,
+        }
+    }
+
+    /// Attempts to allocate the value supplied along with a hook, which is run on the
+    /// value just before it is dropped.
+    ///
+    /// This is useful for cleanup beyond the value's own destructor, like returning a
+    /// resource to an external pool. The hook is stored in the allocation along with the
+    /// value, and runs when the `Hooked` wrapping them is dropped. It isn't run if the
+    /// value is taken out with `Hooked::take` or `Hooked::into_inner`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, HEAP};
+    /// let boxed = HEAP.allocate_with_drop(vec![1, 2], |v| println!("dropping {:?}", v)).ok().unwrap();
+    /// assert_eq!(boxed.len(), 2);
+    /// ```
+    #[inline]
+    fn allocate_with_drop<T, F: FnOnce(&mut T)>(&self, val: T, on_drop: F) -> Result<AllocBox<Hooked<T, F>, Self>, (Error, T)>
+    where Self: Sized
+    {
+        match self.allocate(Hooked::new(val, on_drop)) {
+            Ok(boxed) => Ok(boxed),
+            Err((err, hooked)) => Err((err, hooked.into_inner())),
+        }
+    }
+
+    /// Attempts to allocate the value supplied, coercing it to an unsized type
+    /// like a trait object.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use std::fmt::Debug;
+    /// use allocators::{Allocator, AllocBox, HEAP};
+    ///
+    /// let val: AllocBox<Debug, _> = HEAP.allocate_dyn_as(5u8).ok().unwrap();
+    /// ```
+    #[inline]
+    fn allocate_dyn_as<'a, T: 'a, U: ?Sized + 'a>(&'a self, val: T) -> Result<AllocBox<'a, U, Self>, (Error, T)>
+    where Self: Sized, T: Unsize<U>
+    {
+        match self.allocate(val) {
+            Ok(boxed) => Ok(boxed),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Attempts to allocate the value supplied and pin it in place.
+    ///
+    /// This is like `allocate`, but suited to self-referential types like futures.
+    ///
+    /// # Safety
+    /// The same as for `AllocBox::into_pin`: if the box is leaked rather than dropped,
+    /// this allocator must never reclaim its memory.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, HEAP};
+    /// // the heap only reuses memory which is freed explicitly.
+    /// let mut pinned = unsafe { HEAP.allocate_pinned(5i32).ok().unwrap() };
+    /// *pinned.as_mut() += 1;
+    /// assert_eq!(*pinned, 6);
+    /// ```
+    #[inline]
+    unsafe fn allocate_pinned<T>(&self, val: T) -> Result<Pin<AllocBox<T, Self>>, (Error, T)>
+    where Self: Sized
+    {
+        match self.allocate(val) {
+            Ok(boxed) => Ok(AllocBox::into_pin(boxed)),
+            Err(err) => Err(err),
+        }
+    }
+
     /// Attempts to create a place to allocate into.
     /// For the general purpose, calling `allocate` on the allocator is enough.
     /// However, when you know the value you are allocating is too large
@@ -111,17 +253,104 @@ pub unsafe trait Allocator {
     {
         boxed::make_place(self)
     }
-    
+
+    /// Attempts to allocate a slice holding the items of an iterator, which are moved
+    /// directly into the allocated memory.
+    ///
+    /// Exactly as much memory as the iterator reports with `len` is allocated, and any
+    /// further items are left in it.
+    ///
+    /// # Panics
+    /// Panics if the iterator yields fewer items than it reported. The items already
+    /// collected are dropped and the memory freed, as they are if the iterator panics.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, HEAP};
+    /// let squares = HEAP.collect_in((0..4).map(|i| i * i)).unwrap();
+    /// assert_eq!(&*squares, &[0, 1, 4, 9]);
+    /// ```
+    fn collect_in<T, I: ExactSizeIterator<Item = T>>(&self, iter: I) -> Result<AllocBox<[T], Self>, Error>
+        where Self: Sized
+    {
+        boxed::collect_in(self, iter)
+    }
+
+    /// Moves the elements of a `Vec` into a slice allocated from this, then frees the
+    /// vector's buffer.
+    ///
+    /// The elements are moved with a single bulk copy, rather than one at a time.
+    /// Only as much memory as the vector's length is allocated, whatever its capacity.
+    /// If the allocation fails, the vector is given back intact.
+    fn absorb_vec<T>(&self, vec: Vec<T>) -> Result<AllocBox<[T], Self>, (Error, Vec<T>)>
+        where Self: Sized
+    {
+        boxed::absorb_vec(self, vec)
+    }
+
+    /// Attempts to allocate an array of `N` copies of `value`.
+    ///
+    /// The array is filled in directly in the allocated memory,
+    /// so this works even for arrays too large to be built on the stack.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, HEAP};
+    /// let table = HEAP.allocate_array::<u32, 1_000_000>(7).unwrap();
+    /// assert!(table.iter().all(|&x| x == 7));
+    /// ```
+    fn allocate_array<T: Copy, const N: usize>(&self, value: T) -> Result<AllocBox<[T; N], Self>, Error>
+        where Self: Sized
+    {
+        match self.make_place::<[T; N]>() {
+            Ok(mut place) => {
+                unsafe {
+                    let ptr = place.pointer() as *mut T;
+                    for i in 0..N {
+                        ptr::write(ptr.offset(i as isize), value);
+                    }
+                    Ok(place.finalize())
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Attempts to allocate a slice of `n` default values, aligned to `align`,
+    /// which may be stricter than the alignment of `T`. This is useful for SIMD buffers.
+    ///
+    /// Returns `Error::UnsupportedAlignment` if `align` isn't a power of two
+    /// at least as large as the alignment of `T`.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, HEAP};
+    /// let samples = HEAP.allocate_aligned_slice::<f32>(1024, 32).unwrap();
+    /// assert_eq!(samples.as_ptr() as usize % 32, 0);
+    /// ```
+    fn allocate_aligned_slice<T: Default>(&self, n: usize, align: usize) -> Result<AllocBox<[T], Self>, Error>
+        where Self: Sized
+    {
+        if !align.is_power_of_two() || align < mem::align_of::<T>() {
+            return Err(Error::UnsupportedAlignment);
+        }
+        boxed::collect_aligned_in(self, (0..n).map(|_| T::default()), align)
+    }
+
     /// Attempt to allocate a block of memory.
     ///
     /// Returns either a block of memory allocated
     /// or an Error. If `size` is equal to 0, the block returned must
     /// be created by `Block::empty()`
     ///
+    /// The allocators in this crate reject alignments which aren't a power of two
+    /// with `Error::UnsupportedAlignment`, and those above `MAX_ALIGN`, or above what
+    /// they can support themselves, with `Error::AlignmentTooLarge`.
+    ///
     /// # Safety
     /// Never use the block's pointer outside of the lifetime of the allocator.
     /// It must be deallocated with the same allocator as it was allocated with.
-    /// It is undefined behavior to provide a non power-of-two align.
+    /// Other implementations may treat a non power-of-two align as undefined behavior.
     unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error>;
 
     /// Reallocate a block of memory.
@@ -137,11 +366,271 @@ pub unsafe trait Allocator {
     /// If the requested size is 0, it must deallocate the old block and return an empty one.
     unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)>;
 
+    /// Whether `reallocate_raw` could grow the block to `new_size` without moving it.
+    ///
+    /// This is only a check: nothing is reallocated. The default implementation
+    /// conservatively returns false.
+    ///
+    /// # Safety
+    /// The block must have been allocated by this allocator.
+    unsafe fn can_grow_in_place(&self, _block: &Block, _new_size: usize) -> bool {
+        false
+    }
+
+    /// Reallocate a block of memory, returning a block which spans all the memory usable
+    /// in it, which may be more than `new_size`.
+    ///
+    /// Allocators often have room to spare past the size asked for, like a `FreeList`,
+    /// whose blocks are all the same size. Growable collections can take the size of the
+    /// block returned as their capacity, sparing themselves reallocations to grow into
+    /// memory they already have. The default implementation is just `reallocate_raw`.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw`. The block returned may be reallocated or freed
+    /// with any size from `new_size` up to its own.
+    unsafe fn reallocate_to_usable<'a>(&'a self,
+                                       block: Block<'a>,
+                                       new_size: usize)
+                                       -> Result<Block<'a>, (Error, Block<'a>)> {
+        self.reallocate_raw(block, new_size)
+    }
+
+    /// Whether the memory `allocate_raw` returns is guaranteed to be zeroed, like
+    /// freshly mapped pages.
+    ///
+    /// This is a hint to let callers skip zeroing memory themselves. It says nothing
+    /// about bytes added to a block by reallocation. The default implementation
+    /// conservatively returns false.
+    fn provides_zeroed(&self) -> bool {
+        false
+    }
+
+    /// Allocate a block of memory whose bytes are all zero.
+    ///
+    /// This skips zeroing the block if the allocator `provides_zeroed` memory already.
+    ///
+    /// # Safety
+    /// The same as for `allocate_raw`.
+    unsafe fn allocate_zeroed(&self, size: usize, align: usize) -> Result<Block, Error> {
+        match self.allocate_raw(size, align) {
+            Ok(block) => {
+                if !self.provides_zeroed() && !block.is_empty() {
+                    ptr::write_bytes(block.ptr(), 0, block.size());
+                }
+                Ok(block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reallocate a block of memory, zeroing any bytes added to its end.
+    ///
+    /// This is the same as `reallocate_raw`, except that if the block grows, the bytes
+    /// from its old size up to its new size are zeroed. This is what growable
+    /// zero-initialized buffers need.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw`.
+    unsafe fn reallocate_zeroed<'a>(&'a self,
+                                    block: Block<'a>,
+                                    new_size: usize)
+                                    -> Result<Block<'a>, (Error, Block<'a>)> {
+        let old_size = block.size();
+        match self.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                if new_size > old_size {
+                    ptr::write_bytes(new_block.ptr().offset(old_size as isize), 0, new_size - old_size);
+                }
+                Ok(new_block)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reallocate a block of memory, changing its alignment as well as its size.
+    ///
+    /// If the block is already aligned at least as strictly as requested, this is the same
+    /// as `reallocate_raw`. Otherwise, the default implementation allocates a new block with
+    /// the requested alignment, copies over the first `min(old_size, new_size)` bytes,
+    /// and frees the old block. Allocators that can do better may override this.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw`, except that an empty block is reallocated
+    /// with the new alignment, since it is now known.
+    /// It is undefined behavior to provide a non power-of-two align.
+    unsafe fn reallocate_raw_aligned<'a>(&'a self,
+                                         block: Block<'a>,
+                                         new_size: usize,
+                                         new_align: usize)
+                                         -> Result<Block<'a>, (Error, Block<'a>)> {
+        if block.is_empty() {
+            match self.allocate_raw(new_size, new_align) {
+                Ok(new_block) => Ok(new_block),
+                Err(err) => Err((err, block)),
+            }
+        } else if new_size == 0 || new_align <= block.align() {
+            self.reallocate_raw(block, new_size)
+        } else {
+            copy_reallocate(self, block, new_size, new_align)
+        }
+    }
+
     /// Deallocate the memory referred to by this block.
     ///
     /// # Safety
     /// This block must have been allocated by this allocator.
     unsafe fn deallocate_raw(&self, block: Block);
+
+    /// Attempt to allocate a block of memory described by a `Layout`.
+    ///
+    /// This is the same as `allocate_raw` with the layout's size and alignment.
+    ///
+    /// # Safety
+    /// The same as for `allocate_raw`.
+    unsafe fn allocate_layout(&self, layout: Layout) -> Result<Block, Error> {
+        self.allocate_raw(layout.size(), layout.align())
+    }
+
+    /// Reallocate a block of memory to fit a new `Layout`.
+    ///
+    /// This is the same as `reallocate_raw_aligned` with the layout's size and alignment.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw_aligned`.
+    unsafe fn reallocate_layout<'a>(&'a self,
+                                    block: Block<'a>,
+                                    new_layout: Layout)
+                                    -> Result<Block<'a>, (Error, Block<'a>)> {
+        self.reallocate_raw_aligned(block, new_layout.size(), new_layout.align())
+    }
+
+    /// Deallocate the memory at `ptr`, which was allocated with the given `Layout`.
+    ///
+    /// # Safety
+    /// The memory must have been allocated by this allocator with exactly this layout.
+    unsafe fn deallocate_layout(&self, ptr: *mut u8, layout: Layout) {
+        if layout.size() != 0 {
+            self.deallocate_raw(Block::new(ptr, layout.size(), layout.align()))
+        }
+    }
+
+    /// Summarizes this allocator's usage.
+    ///
+    /// Allocators which keep track of their usage fill this in, and allocators composed of
+    /// others merge their reports in. The default implementation returns an empty report.
+    fn report(&self) -> Report {
+        Report::default()
+    }
+
+    /// Reallocate a block of memory by copying it into a new block, taken from `scratch`
+    /// if this allocator is out of memory.
+    ///
+    /// This allocates a new block from this allocator, or failing that from `scratch`,
+    /// copies over the first `min(old_size, new_size)` bytes, and only then frees the old
+    /// block. Along with the new block, this returns whether it came from `scratch`, in which
+    /// case it must be freed there. If neither allocator can provide it, the old block is
+    /// given back untouched.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw`.
+    unsafe fn reallocate_via<'a, S: Allocator>(&'a self,
+                                               block: Block<'a>,
+                                               new_size: usize,
+                                               scratch: &'a S)
+                                               -> Result<(Block<'a>, bool), (Error, Block<'a>)>
+        where Self: Sized
+    {
+        if new_size == 0 {
+            self.deallocate_raw(block);
+            return Ok((Block::empty(), false));
+        } else if block.is_empty() {
+            return Err((Error::UnsupportedAlignment, block));
+        }
+
+        let align = block.align();
+        let (new_block, from_scratch) = match self.allocate_raw(new_size, align) {
+            Ok(new_block) => (new_block, false),
+            Err(_) => {
+                match scratch.allocate_raw(new_size, align) {
+                    Ok(new_block) => (new_block, true),
+                    Err(err) => return Err((err, block)),
+                }
+            }
+        };
+
+        ptr::copy_nonoverlapping(block.ptr(), new_block.ptr(), cmp::min(block.size(), new_size));
+        self.deallocate_raw(block);
+        Ok((new_block, from_scratch))
+    }
+}
+
+/// A summary of an allocator's usage, as returned by `Allocator::report`.
+///
+/// The reports of allocators composed of others contain their reports as `children`,
+/// and their own figures are the totals of their children's.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// The name of the allocator this describes.
+    pub name: &'static str,
+    /// The number of bytes currently allocated.
+    pub bytes_outstanding: usize,
+    /// The number of successful allocations.
+    pub allocations: usize,
+    /// The number of failed allocations.
+    pub failures: usize,
+    /// The reports of the allocators this is composed of.
+    pub children: Vec<Report>,
+}
+
+impl Report {
+    /// Creates an empty report for the named allocator.
+    pub fn new(name: &'static str) -> Self {
+        Report { name: name, ..Report::default() }
+    }
+
+    /// Adds a child's report to this one, including its figures in this one's totals.
+    pub fn merge(&mut self, child: Report) {
+        self.bytes_outstanding += child.bytes_outstanding;
+        self.allocations += child.allocations;
+        self.failures += child.failures;
+        self.children.push(child);
+    }
+
+    /// The fraction of allocation attempts which succeeded,
+    /// or `None` if there haven't been any.
+    pub fn hit_rate(&self) -> Option<f64> {
+        let attempts = self.allocations + self.failures;
+        if attempts == 0 {
+            None
+        } else {
+            Some(self.allocations as f64 / attempts as f64)
+        }
+    }
+}
+
+/// Attempts to allocate the value supplied using any allocator, including trait objects.
+///
+/// `Allocator::allocate` is only available for sized allocators, so this is the way to
+/// allocate typed values through a `&Allocator`. The returned box borrows the allocator,
+/// so it can't outlive the reference it was allocated through.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{allocate_dyn, Allocator, HEAP};
+///
+/// let alloc: &Allocator = HEAP;
+/// let val = allocate_dyn(alloc, 5i32).ok().unwrap();
+/// assert_eq!(*val, 5);
+/// ```
+pub fn allocate_dyn<'a, T, A: ?Sized + Allocator>(alloc: &'a A, val: T) -> Result<AllocBox<'a, T, A>, (Error, T)> {
+    match boxed::make_place(alloc) {
+        Ok(place) => {
+            Ok(in place { val })
+        }
+        Err(err) => {
+            Err((err, val))
+        }
+    }
 }
 
 /// An allocator that knows which blocks have been issued by it.
@@ -152,7 +641,27 @@ pub trait BlockOwner: Allocator {
     }
 
     /// Whether this allocator owns the block passed to it.
-    fn owns_block(&self, block: &Block) -> bool;
+    fn owns_block(&self, block: &Block) -> bool {
+        self.owns_ptr(block.ptr())
+    }
+
+    /// Whether this allocator owns the memory at the pointer passed to it, which should
+    /// be the start of a block. This works where only a raw pointer is at hand,
+    /// like one passed through FFI.
+    fn owns_ptr(&self, ptr: *mut u8) -> bool;
+
+    /// Frees every block this allocator has issued at once.
+    ///
+    /// This is faster than freeing values one by one when none of them are in use anymore.
+    /// The default implementation returns an error, for allocators which can't do this.
+    ///
+    /// # Safety
+    /// None of the memory this allocator has issued may be used afterwards,
+    /// including through any outstanding `AllocBox`es, which must be forgotten
+    /// rather than dropped.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        Err(Error::AllocatorSpecific("Allocator can't deallocate all blocks at once.".into()))
+    }
 
     /// Joins this allocator with a fallback allocator.
     // TODO: Maybe not the right place for this?
@@ -165,6 +674,29 @@ pub trait BlockOwner: Allocator {
     }
 }
 
+/// An allocator which knows how much more memory it can give out.
+///
+/// This lets generic code check whether a batch of allocations will fit before starting it.
+pub trait CapacityAware: Allocator {
+    /// The number of bytes which can still be allocated, or `None` if this is unbounded.
+    ///
+    /// Alignment may cause fewer bytes to be available than reported.
+    fn remaining(&self) -> Option<usize>;
+}
+
+/// An allocator which can reclaim all of its allocations at once.
+///
+/// This lets generic code bulk-free any arena-like allocator, e.g. at the end of a frame.
+pub trait Reset: Allocator {
+    /// Resets the allocator, reclaiming everything that has been allocated from it.
+    ///
+    /// # Safety
+    /// No memory allocated before the reset may be used afterwards.
+    /// Outstanding `AllocBox`es must not even be dropped, since their memory may have been
+    /// handed out again.
+    unsafe fn reset(&self);
+}
+
 /// A block of memory created by an allocator.
 pub struct Block<'a> {
     ptr: Unique<u8>,
@@ -215,23 +747,123 @@ impl<'a> Block<'a> {
     pub fn is_empty(&self) -> bool {
         self.size == 0
     }
+
+    /// Get the layout of this block. Empty blocks have a zero-sized layout
+    /// with an alignment of 1.
+    pub fn layout(&self) -> Layout {
+        if self.is_empty() {
+            Layout::new::<()>()
+        } else {
+            unsafe { Layout::from_size_align_unchecked(self.size, self.align) }
+        }
+    }
+
+    /// View this block as a slice of as many `T`s as fit in it.
+    ///
+    /// Empty blocks and zero-sized `T`s give a dangling, empty slice. Dereferencing
+    /// the result is only valid if the block is aligned for `T` and its contents are
+    /// initialized.
+    pub fn as_non_null_slice<T>(&self) -> NonNull<[T]> {
+        let elem_size = mem::size_of::<T>();
+        let (ptr, len) = if self.is_empty() || elem_size == 0 {
+            (NonNull::<T>::dangling().as_ptr(), 0)
+        } else {
+            (self.ptr() as *mut T, self.size / elem_size)
+        };
+        unsafe { NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(ptr, len)) }
+    }
+
+    /// How many bytes this block's pointer lies past the last `target_align` boundary,
+    /// or 0 if it is aligned to `target_align`.
+    ///
+    /// # Panics
+    /// Panics if `target_align` is zero.
+    pub fn alignment_offset(&self, target_align: usize) -> usize {
+        self.ptr() as usize % target_align
+    }
+
+    /// Whether this block ends exactly where `other` begins, so the two could
+    /// be merged into a single block.
+    pub fn is_adjacent_before(&self, other: &Block) -> bool {
+        self.ptr() as usize + self.size == other.ptr() as usize
+    }
+
+    /// Whether this block begins exactly where `other` ends. This is the same as
+    /// `other.is_adjacent_before(self)`.
+    pub fn is_adjacent_after(&self, other: &Block) -> bool {
+        other.is_adjacent_before(self)
+    }
+
+    /// Whether `ptr` points to one of this block's bytes. The byte just past the end
+    /// isn't part of the block, so an empty block contains nothing.
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        let (start, addr) = (self.ptr() as usize, ptr as usize);
+        addr >= start && addr - start < self.size
+    }
+}
+
+impl<'a> fmt::Debug for Block<'a> {
+    /// Shows the block's pointer, size, and alignment, without touching its memory.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            f.write_str("Block { empty }")
+        } else {
+            f.debug_struct("Block")
+             .field("ptr", &self.ptr())
+             .field("size", &self.size)
+             .field("align", &self.align)
+             .finish()
+        }
+    }
 }
 
+/// The largest alignment any allocator in this crate supports.
+///
+/// Requests aligned to more than this fail cleanly with `Error::AlignmentTooLarge`,
+/// however much memory is left. Many allocators support less: see their docs.
+pub const MAX_ALIGN: usize = 1 << 29;
+
 /// Errors that can occur while creating an allocator
 /// or allocating from it.
 #[derive(Debug, Eq, PartialEq)]
 pub enum Error {
     /// The allocator failed to allocate the amount of memory requested of it.
-    OutOfMemory,
+    OutOfMemory {
+        /// The size of the request which failed, or `usize::MAX` if computing it overflowed.
+        requested: usize,
+        /// The alignment of the request which failed.
+        align: usize,
+    },
     /// The allocator does not support the requested alignment.
     UnsupportedAlignment,
+    /// The requested alignment is larger than the largest the allocator supports.
+    AlignmentTooLarge {
+        /// The alignment requested.
+        requested: usize,
+        /// The largest alignment supported.
+        max: usize,
+    },
     /// An allocator-specific error message.
     AllocatorSpecific(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str(self.description())
+        match *self {
+            Error::OutOfMemory { requested, align } => {
+                write!(formatter,
+                       "Allocator out of memory: failed to allocate {} bytes aligned to {}.",
+                       requested,
+                       align)
+            }
+            Error::AlignmentTooLarge { requested, max } => {
+                write!(formatter,
+                       "Requested alignment of {} exceeds the maximum of {}.",
+                       requested,
+                       max)
+            }
+            _ => formatter.write_str(self.description()),
+        }
     }
 }
 
@@ -240,12 +872,15 @@ impl StdError for Error {
         use Error::*;
 
         match *self {
-            OutOfMemory => {
+            OutOfMemory { .. } => {
                 "Allocator out of memory."
             }
             UnsupportedAlignment => {
                 "Attempted to allocate with unsupported alignment."
             }
+            AlignmentTooLarge { .. } => {
+                "Attempted to allocate with an alignment larger than supported."
+            }
             AllocatorSpecific(ref reason) => {
                 reason
             }
@@ -264,15 +899,56 @@ pub struct HeapAllocator;
 // Values allocated with this are effectively `Box`es.
 pub const HEAP: &'static HeapAllocator = &HeapAllocator;
 
+impl HeapAllocator {
+    /// Shrinks a block, keeping its pointer stable if at all possible.
+    ///
+    /// `reallocate_raw` is free to move an allocation even when it shrinks.
+    /// This first asks the heap to resize the allocation in place, and only falls back
+    /// to a moving reallocation if that fails. On success, returns the new block
+    /// along with whether it was moved.
+    ///
+    /// Requests which are not shrinks are forwarded to `reallocate_raw`.
+    ///
+    /// # Safety
+    /// The same as for `reallocate_raw`.
+    pub unsafe fn shrink_in_place<'a>(&'a self,
+                                      block: Block<'a>,
+                                      new_size: usize)
+                                      -> Result<(Block<'a>, bool), (Error, Block<'a>)> {
+        let old_ptr = block.ptr();
+
+        if new_size != 0 && !block.is_empty() && new_size <= block.size() {
+            let usable = heap::reallocate_inplace(old_ptr, block.size(), new_size, block.align());
+            if usable == heap::usable_size(new_size, block.align()) {
+                return Ok((Block::new(old_ptr, new_size, block.align()), false));
+            }
+        }
+
+        match self.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                let moved = new_block.ptr() != old_ptr;
+                Ok((new_block, moved))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
 unsafe impl Allocator for HeapAllocator {
     #[inline]
     unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = check_align(align) {
+            return Err(err);
+        }
         if size != 0 {
             let ptr = heap::allocate(size, align);
             if !ptr.is_null() {
                 Ok(Block::new(ptr, size, align))
             } else {
-                Err(Error::OutOfMemory)
+                Err(Error::OutOfMemory {
+                    requested: size,
+                    align: align,
+                })
             }
         } else {
             Ok(Block::empty())
@@ -290,13 +966,35 @@ unsafe impl Allocator for HeapAllocator {
             let new_ptr = heap::reallocate(block.ptr(), block.size(), new_size, block.align());
 
             if new_ptr.is_null() {
-                Err((Error::OutOfMemory, block))
+                let align = block.align();
+                Err((Error::OutOfMemory {
+                    requested: new_size,
+                    align: align,
+                }, block))
             } else {
                 Ok(Block::new(new_ptr, new_size, block.align()))
             }
         }
     }
 
+    /// The heap may round allocations up, and reports by how much.
+    unsafe fn reallocate_to_usable<'a>(&'a self,
+                                       block: Block<'a>,
+                                       new_size: usize)
+                                       -> Result<Block<'a>, (Error, Block<'a>)> {
+        match self.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                if new_block.is_empty() {
+                    Ok(new_block)
+                } else {
+                    let usable = heap::usable_size(new_size, new_block.align());
+                    Ok(Block::new(new_block.ptr(), usable, new_block.align()))
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     #[inline]
     unsafe fn deallocate_raw(&self, block: Block) {
         if !block.is_empty() {
@@ -305,6 +1003,44 @@ unsafe impl Allocator for HeapAllocator {
     }
 }
 
+impl CapacityAware for HeapAllocator {
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+}
+
+// allocates a new block from the allocator with the given alignment, copies over
+// as much of the old block as fits, and frees the old block.
+unsafe fn copy_reallocate<'a, A: ?Sized + Allocator>(alloc: &'a A,
+                                                     block: Block<'a>,
+                                                     new_size: usize,
+                                                     new_align: usize)
+                                                     -> Result<Block<'a>, (Error, Block<'a>)> {
+    match alloc.allocate_raw(new_size, new_align) {
+        Ok(new_block) => {
+            ptr::copy_nonoverlapping(block.ptr(), new_block.ptr(), cmp::min(block.size(), new_size));
+            alloc.deallocate_raw(block);
+            Ok(new_block)
+        }
+        Err(err) => Err((err, block)),
+    }
+}
+
+// checks that an alignment is a power of two no larger than `MAX_ALIGN`.
+#[inline]
+fn check_align(align: usize) -> Result<(), Error> {
+    if !align.is_power_of_two() {
+        Err(Error::UnsupportedAlignment)
+    } else if align > MAX_ALIGN {
+        Err(Error::AlignmentTooLarge {
+            requested: align,
+            max: MAX_ALIGN,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 // aligns a pointer forward to the next value aligned with `align`.
 #[inline]
 fn align_forward(ptr: *mut u8, align: usize) -> *mut u8 {
@@ -322,9 +1058,29 @@ unsafe impl<'a, A: ?Sized + Allocator + 'a> Allocator for Box<A> {
         (**self).reallocate_raw(block, new_size)
     }
 
+    unsafe fn reallocate_raw_aligned<'b>(&'b self, block: Block<'b>, new_size: usize, new_align: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+        (**self).reallocate_raw_aligned(block, new_size, new_align)
+    }
+
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        (**self).can_grow_in_place(block, new_size)
+    }
+
+    unsafe fn reallocate_to_usable<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+        (**self).reallocate_to_usable(block, new_size)
+    }
+
     unsafe fn deallocate_raw(&self, block: Block) {
         (**self).deallocate_raw(block)
     }
+
+    fn provides_zeroed(&self) -> bool {
+        (**self).provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        (**self).report()
+    }
 }
 
 unsafe impl<'a, 'b: 'a, A: ?Sized + Allocator + 'b> Allocator for &'a A {
@@ -336,9 +1092,29 @@ unsafe impl<'a, 'b: 'a, A: ?Sized + Allocator + 'b> Allocator for &'a A {
         (**self).reallocate_raw(block, new_size)
     }
 
+    unsafe fn reallocate_raw_aligned<'c>(&'c self, block: Block<'c>, new_size: usize, new_align: usize) -> Result<Block<'c>, (Error, Block<'c>)> {
+        (**self).reallocate_raw_aligned(block, new_size, new_align)
+    }
+
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        (**self).can_grow_in_place(block, new_size)
+    }
+
+    unsafe fn reallocate_to_usable<'c>(&'c self, block: Block<'c>, new_size: usize) -> Result<Block<'c>, (Error, Block<'c>)> {
+        (**self).reallocate_to_usable(block, new_size)
+    }
+
     unsafe fn deallocate_raw(&self, block: Block) {
         (**self).deallocate_raw(block)
     }
+
+    fn provides_zeroed(&self) -> bool {
+        (**self).provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        (**self).report()
+    }
 }
 
 unsafe impl<'a, 'b: 'a, A: ?Sized + Allocator + 'b> Allocator for &'a mut A {
@@ -350,9 +1126,29 @@ unsafe impl<'a, 'b: 'a, A: ?Sized + Allocator + 'b> Allocator for &'a mut A {
         (**self).reallocate_raw(block, new_size)
     }
 
+    unsafe fn reallocate_raw_aligned<'c>(&'c self, block: Block<'c>, new_size: usize, new_align: usize) -> Result<Block<'c>, (Error, Block<'c>)> {
+        (**self).reallocate_raw_aligned(block, new_size, new_align)
+    }
+
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        (**self).can_grow_in_place(block, new_size)
+    }
+
+    unsafe fn reallocate_to_usable<'c>(&'c self, block: Block<'c>, new_size: usize) -> Result<Block<'c>, (Error, Block<'c>)> {
+        (**self).reallocate_to_usable(block, new_size)
+    }
+
     unsafe fn deallocate_raw(&self, block: Block) {
         (**self).deallocate_raw(block)
     }
+
+    fn provides_zeroed(&self) -> bool {
+        (**self).provides_zeroed()
+    }
+
+    fn report(&self) -> Report {
+        (**self).report()
+    }
 }
 
 #[cfg(test)]
@@ -391,11 +1187,819 @@ mod tests {
         let _: AllocBox<Bomb, _> = my_foo.downcast().ok().unwrap();
     }
 
+    #[test]
+    fn block_as_slice() {
+        unsafe {
+            let block = HEAP.allocate_raw(16, 8).unwrap();
+            assert_eq!(block.as_non_null_slice::<u32>().len(), 4);
+            assert_eq!(block.as_non_null_slice::<[u8; 3]>().len(), 5);
+            assert_eq!(block.as_non_null_slice::<()>().len(), 0);
+            assert_eq!(block.as_non_null_slice::<u64>().as_ptr() as *mut u8, block.ptr());
+            HEAP.deallocate_raw(block);
+        }
+        assert_eq!(Block::empty().as_non_null_slice::<u64>().len(), 0);
+    }
+
+    #[test]
+    fn same_allocator() {
+        let (first, second) = (Scoped::new(64).unwrap(), Scoped::new(64).unwrap());
+        let a = first.allocate(1u8).unwrap();
+        let b = first.allocate([2u32; 2]).unwrap();
+        let c = second.allocate(3u8).unwrap();
+        assert!(a.same_allocator(&b));
+        assert!(!a.same_allocator(&c));
+    }
+
+    #[test]
+    fn collect_in() {
+        let alloc = Scoped::new(128).unwrap();
+        let strings = alloc.collect_in((0..3).map(|i| i.to_string())).unwrap();
+        assert_eq!(&*strings, &["0", "1", "2"]);
+        let units = alloc.collect_in(vec![(), ()].into_iter()).unwrap();
+        assert_eq!(units.len(), 2);
+        let empty = alloc.collect_in(Vec::<u64>::new().into_iter()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn absorb_vec() {
+        let alloc = Scoped::new(128).unwrap();
+        let mut strings: Vec<String> = (0..3).map(|i| i.to_string()).collect();
+        strings.reserve(16);
+        let ptr = strings[1].as_ptr();
+        let absorbed = alloc.absorb_vec(strings).ok().unwrap();
+        assert_eq!(&*absorbed, &["0", "1", "2"]);
+        // the strings themselves moved, not their contents.
+        assert_eq!(absorbed[1].as_ptr(), ptr);
+        assert_eq!(alloc.remaining(), Some(128 - 3 * mem::size_of::<String>()));
+
+        let (err, back) = alloc.absorb_vec(vec![0u64; 32]).err().unwrap();
+        assert_eq!(err,
+                   Error::OutOfMemory {
+                       requested: 256,
+                       align: mem::align_of::<u64>(),
+                   });
+        assert_eq!(back, vec![0u64; 32]);
+        assert!(alloc.absorb_vec(Vec::<u8>::new()).ok().unwrap().is_empty());
+    }
+
+    #[test]
+    fn collect_in_panic() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::rc::Rc;
+
+        let alloc = Scoped::new(64).unwrap();
+        let counted = Rc::new(());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let iter = (0..4).map(|i| if i == 2 { panic!("boom") } else { counted.clone() });
+            let _ = alloc.collect_in(iter);
+        }));
+        assert!(result.is_err());
+        assert_eq!(Rc::strong_count(&counted), 1);
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    fn owned_box() {
+        fn make_names() -> OwnedBox<Vec<String>, Scoped<'static, HeapAllocator>> {
+            let alloc = Scoped::new(64).unwrap();
+            let names = vec!["a".to_string(), "b".to_string()];
+            unsafe { OwnedBox::new(names, alloc).ok().unwrap() }
+        }
+
+        let mut names = make_names();
+        names.push("c".to_string());
+        assert_eq!(names.len(), 3);
+        assert_eq!(names.allocator().remaining(), Some(64 - mem::size_of::<Vec<String>>()));
+
+        let unit = unsafe { OwnedBox::new((), Scoped::new(0).unwrap()).ok().unwrap() };
+        assert_eq!(*unit, ());
+    }
+
+    #[test]
+    fn realloc_boxed_bytes() {
+        let boxed = HEAP.collect_in(0..4u8).unwrap();
+        let boxed = boxed.realloc(4096).ok().unwrap();
+        assert_eq!(&boxed[..5], &[0, 1, 2, 3, 0]);
+        assert!(boxed[4..].iter().all(|&b| b == 0));
+        let boxed = boxed.realloc(2).ok().unwrap();
+        assert_eq!(&*boxed, &[0, 1]);
+        assert_eq!(boxed.realloc(0).ok().unwrap().len(), 0);
+
+        let alloc = Scoped::new(32).unwrap();
+        let boxed = alloc.collect_in(0..8u8).unwrap();
+        let ptr = boxed.as_ptr();
+        let boxed = boxed.realloc(16).ok().unwrap();
+        assert_eq!(boxed.as_ptr(), ptr);
+        assert_eq!(&boxed[6..10], &[6, 7, 0, 0]);
+        let (err, boxed) = boxed.realloc(64).err().unwrap();
+        assert_eq!(err, Error::OutOfMemory { requested: 64, align: 1 });
+        assert_eq!(boxed.len(), 16);
+        let boxed = boxed.realloc(4).ok().unwrap();
+        assert_eq!(&*boxed, &[0, 1, 2, 3]);
+        assert_eq!(alloc.remaining(), Some(28));
+    }
+
+    #[test]
+    fn place_unwind() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Bomb(u64);
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let alloc = Scoped::new(64).unwrap();
+        let first = alloc.allocate(Bomb(1)).ok().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = in alloc.make_place().unwrap() {
+                if first.0 == 1 {
+                    panic!("boom");
+                }
+                Bomb(2)
+            };
+        }));
+        assert!(result.is_err());
+
+        // the place was the most recent allocation, so its memory was reclaimed
+        // without dropping the value that was never written.
+        assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+        assert_eq!(alloc.remaining(), Some(56));
+        let second = alloc.allocate(Bomb(3)).ok().unwrap();
+        assert_eq!(&*second as *const Bomb as usize,
+                   &*first as *const Bomb as usize + 8);
+        drop((second, first));
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "fewer than the 3 it reported")]
+    fn collect_in_short() {
+        struct Liar(usize);
+        impl Iterator for Liar {
+            type Item = u8;
+            fn next(&mut self) -> Option<u8> {
+                if self.0 == 0 {
+                    None
+                } else {
+                    self.0 -= 1;
+                    Some(0)
+                }
+            }
+        }
+        impl ExactSizeIterator for Liar {
+            fn len(&self) -> usize {
+                3
+            }
+        }
+
+        let _ = HEAP.collect_in(Liar(1));
+    }
+
+    #[test]
+    fn reuse_place() {
+        let alloc = Scoped::new(mem::size_of::<Vec<i32>>()).unwrap();
+        let boxed = alloc.allocate(vec![1, 2, 3]).unwrap();
+        let old_ptr = &*boxed as *const _ as usize;
+        let (val, place) = boxed.into_place();
+        assert_eq!(val, vec![1, 2, 3]);
+
+        let boxed = place.try_finalize::<(), _>(|ptr| {
+                             unsafe { ptr::write(ptr, vec![4]) };
+                             Ok(())
+                         })
+                         .unwrap();
+        assert_eq!(&*boxed as *const _ as usize, old_ptr);
+        assert_eq!(*boxed, vec![4]);
+        drop(boxed);
+
+        let (_, place) = alloc.allocate(vec![5]).unwrap().into_place();
+        drop(place);
+        assert!(alloc.allocate(Vec::<i32>::new()).is_ok());
+    }
+
+    #[test]
+    fn zero_sized() {
+        use std::cell::Cell;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Logger<'a>(&'a Cell<usize>);
+        impl<'a> ProxyLogger for Logger<'a> {
+            fn allocate_success(&self, _block: &Block) {
+                self.0.set(self.0.get() + 1);
+            }
+            fn allocate_fail(&self, _err: &Error, _size: usize, _align: usize) {}
+            fn deallocate(&self, _block: &Block) {
+                self.0.set(self.0.get() + 1);
+            }
+            fn reallocate_success(&self, _old_block: &Block, _new_block: &Block) {}
+            fn reallocate_fail(&self, _err: &Error, _block: &Block, _req_size: usize) {}
+        }
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        #[repr(align(16))]
+        struct Bomb;
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let calls = Cell::new(0);
+        let alloc = Proxy::new(HEAP, Logger(&calls));
+        let boxed = alloc.allocate(Bomb).ok().unwrap();
+        assert_eq!(&*boxed as *const _ as usize % 16, 0);
+        drop(boxed);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        drop(alloc.allocate(Bomb).ok().unwrap().take());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+        assert_eq!(calls.get(), 0);
+    }
+
+    #[test]
+    fn try_allocate() {
+        let alloc = Scoped::new(4).unwrap();
+        let first = alloc.try_allocate(1u32);
+        assert_eq!(first.as_ref().map(|b| **b), Some(1));
+        assert!(alloc.try_allocate(2u32).is_none());
+    }
+
+    #[test]
+    fn drop_hook() {
+        use std::cell::Cell;
+
+        let hooked = Cell::new(0);
+        let boxed = HEAP.allocate_with_drop(5u32, |v| hooked.set(hooked.get() + *v)).ok().unwrap();
+        assert_eq!(**boxed, 5);
+        drop(boxed);
+        assert_eq!(hooked.get(), 5);
+
+        let boxed = HEAP.allocate_with_drop(7u32, |v| hooked.set(hooked.get() + *v)).ok().unwrap();
+        assert_eq!(Hooked::take(boxed), 7);
+        assert_eq!(hooked.get(), 5);
+
+        // taking the box leaves the hook armed until the wrapper is dropped.
+        let boxed = HEAP.allocate_with_drop(8u32, |v| hooked.set(hooked.get() + *v)).ok().unwrap();
+        let taken = boxed.take();
+        assert_eq!(hooked.get(), 5);
+        drop(taken);
+        assert_eq!(hooked.get(), 13);
+
+        // the hook is dropped without running when discarded.
+        let token = ::std::rc::Rc::new(());
+        let (held, counter) = (token.clone(), &hooked);
+        let boxed = HEAP.allocate_with_drop(9u32, move |v| {
+                            let _held = &held;
+                            counter.set(counter.get() + *v);
+                        })
+                        .ok()
+                        .unwrap();
+        assert_eq!(boxed.take().into_inner(), 9);
+        assert_eq!(hooked.get(), 13);
+        assert_eq!(::std::rc::Rc::strong_count(&token), 1);
+    }
+
+    #[test]
+    fn downcast_unchecked() {
+        let any: AllocBox<Any, _> = HEAP.allocate(17u32).unwrap();
+        let num: AllocBox<u32, _> = unsafe { any.downcast_unchecked() };
+        assert_eq!(*num, 17);
+    }
+
     #[test]
     fn take_out() {
         let _: [u8; 1024] = HEAP.allocate([0; 1024]).ok().unwrap().take();
     }
 
+    #[test]
+    fn heap_shrink_in_place() {
+        unsafe {
+            let block = HEAP.allocate_raw(1024, 8).unwrap();
+            ptr::write_bytes(block.ptr(), 7, 16);
+            let old_ptr = block.ptr();
+            let (block, moved) = HEAP.shrink_in_place(block, 16).ok().unwrap();
+            assert_eq!(block.size(), 16);
+            // the heap shrinks a block of this size without moving it.
+            assert!(!moved);
+            assert_eq!(block.ptr(), old_ptr);
+            assert!(::std::slice::from_raw_parts(block.ptr(), 16).iter().all(|&b| b == 7));
+            HEAP.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    fn slice_raw_parts() {
+        let boxed: AllocBox<[u32], _> = HEAP.allocate([1, 2, 3]).unwrap();
+        let (ptr, len) = boxed.into_raw_parts();
+        assert_eq!(len, 3);
+
+        let boxed = unsafe { AllocBox::from_raw_parts(ptr, len, HEAP) };
+        assert_eq!(&*boxed, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn allocate_through_trait_object() {
+        let alloc: &Allocator = HEAP;
+        let val = allocate_dyn(alloc, [1u8; 32]).ok().unwrap();
+        assert_eq!(val.len(), 32);
+    }
+
+    #[test]
+    fn reallocate_through_scratch() {
+        let alloc = FreeList::new(16, 1).unwrap();
+        unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            *block.ptr() = 42;
+            // the only block is taken, so the new one comes from the scratch allocator.
+            let (block, from_scratch) = alloc.reallocate_via(block, 32, HEAP).ok().unwrap();
+            assert!(from_scratch);
+            assert_eq!(*block.ptr(), 42);
+            assert_eq!(block.size(), 32);
+            assert_eq!(alloc.free(), 1);
+            HEAP.deallocate_raw(block);
+
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            *block.ptr() = 7;
+            let ptr = block.ptr();
+            // the block is given back untouched if neither allocator can help.
+            let (_, block) = alloc.reallocate_via(block, 32, &NullAllocator).err().unwrap();
+            assert_eq!(block.ptr(), ptr);
+            assert_eq!(*block.ptr(), 7);
+            assert_eq!(alloc.free(), 0);
+            alloc.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    fn generic_reset() {
+        fn end_frame<A: Reset>(alloc: &A) {
+            unsafe { alloc.reset() }
+        }
+
+        let alloc = Scoped::new(8).unwrap();
+        let val = alloc.allocate(0u64).unwrap();
+        ::std::mem::forget(val);
+        assert!(alloc.allocate(0u64).is_err());
+
+        end_frame(&alloc);
+        assert!(alloc.allocate(0u64).is_ok());
+    }
+
+    #[test]
+    fn large_array() {
+        let big = HEAP.allocate_array::<u64, 1_000_000>(3).unwrap();
+        assert!(big.iter().all(|&x| x == 3));
+
+        let alloc = Scoped::new(64).unwrap();
+        let small = alloc.allocate_array::<u16, 32>(1).unwrap();
+        assert_eq!(small.iter().map(|&x| x as usize).sum::<usize>(), 32);
+    }
+
+    #[test]
+    fn pinned() {
+        use std::marker::PhantomPinned;
+
+        struct SelfRef {
+            val: u32,
+            ptr: *const u32,
+            _pin: PhantomPinned,
+        }
+
+        let mut pinned = unsafe {
+            HEAP.allocate_pinned(SelfRef {
+                    val: 5,
+                    ptr: ::std::ptr::null(),
+                    _pin: PhantomPinned,
+                })
+                .ok()
+                .unwrap()
+        };
+        unsafe {
+            let inner = pinned.as_mut().get_unchecked_mut();
+            inner.ptr = &inner.val;
+        }
+        assert_eq!(unsafe { *pinned.ptr }, 5);
+
+        let mut unpinned = unsafe { HEAP.allocate_pinned(1u8).ok().unwrap() };
+        *unpinned.as_mut() += 1;
+        assert_eq!(*unpinned, 2);
+    }
+
+    #[test]
+    fn reallocate_stricter_alignment() {
+        fn grow<A: Allocator>(alloc: &A) {
+            unsafe {
+                // make sure the first block is misaligned for 32.
+                let pad = alloc.allocate_raw(1, 1).unwrap();
+                let block = alloc.allocate_raw(1, 1).unwrap();
+                *block.ptr() = 7;
+
+                let block = alloc.reallocate_raw_aligned(block, 64, 32).ok().unwrap();
+                assert_eq!(block.ptr() as usize % 32, 0);
+                assert_eq!(block.align(), 32);
+                assert_eq!(*block.ptr(), 7);
+                alloc.deallocate_raw(block);
+                alloc.deallocate_raw(pad);
+            }
+        }
+
+        grow(HEAP);
+        grow(&Scoped::new(256).unwrap());
+    }
+
+    #[test]
+    fn composed_report() {
+        let alloc = Fallback::new(Scoped::new(8).unwrap(), Scoped::new(64).unwrap());
+        let _a = alloc.allocate(0u64).unwrap();
+        let _b = alloc.allocate(0u64).unwrap();
+
+        let report = alloc.report();
+        assert_eq!(report.children.len(), 2);
+        assert_eq!(report.children[0].bytes_outstanding, 8);
+        assert_eq!(report.children[0].failures, 1);
+        assert_eq!(report.children[1].bytes_outstanding, 8);
+        assert_eq!(report.bytes_outstanding, 16);
+        assert_eq!(report.allocations, 2);
+        assert_eq!(report.children[0].hit_rate(), Some(0.5));
+    }
+
+    #[test]
+    fn adjacent_blocks() {
+        let mut buf = [0u8; 16];
+        let ptr = buf.as_mut_ptr();
+        let first = Block::new(ptr, 8, 1);
+        let second = Block::new(unsafe { ptr.offset(8) }, 8, 1);
+        assert!(first.is_adjacent_before(&second));
+        assert!(!second.is_adjacent_before(&first));
+        assert!(second.is_adjacent_after(&first));
+        assert!(!first.is_adjacent_after(&second));
+
+        // a gap of a single byte is enough to break adjacency either way.
+        let gapped = Block::new(unsafe { ptr.offset(9) }, 7, 1);
+        assert!(!first.is_adjacent_before(&gapped));
+        assert!(!gapped.is_adjacent_after(&first));
+    }
+
+    #[test]
+    fn block_contains() {
+        let mut buf = [0u8; 16];
+        let ptr = buf.as_mut_ptr();
+        let block = Block::new(unsafe { ptr.offset(4) }, 8, 1);
+        unsafe {
+            assert!(!block.contains(ptr.offset(3)));
+            assert!(block.contains(ptr.offset(4)));
+            assert!(block.contains(ptr.offset(11)));
+            assert!(!block.contains(ptr.offset(12)));
+        }
+        assert!(!Block::empty().contains(Block::empty().ptr()));
+    }
+
+    #[test]
+    fn array_as_slice() {
+        let mut buf = HEAP.allocate([0u8; 32]).unwrap();
+        buf.as_mut_slice()[..4].copy_from_slice(b"abcd");
+        assert_eq!(&buf.as_slice()[..4], b"abcd");
+        assert_eq!(buf.as_slice().len(), 32);
+    }
+
+    #[test]
+    fn drop_boxed_slice() {
+        use std::cell::Cell;
+
+        struct Counted<'a>(&'a Cell<usize>);
+        impl<'a> Drop for Counted<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let drops = Cell::new(0);
+        let alloc = Scoped::new(64).unwrap();
+        {
+            let array = alloc.allocate([Counted(&drops), Counted(&drops), Counted(&drops)])
+                             .ok()
+                             .unwrap();
+            let slice: AllocBox<[Counted], _> = array;
+            assert_eq!(slice.len(), 3);
+        }
+        assert_eq!(drops.get(), 3);
+        // the whole block was freed, so the allocator is empty again.
+        assert!(alloc.allocate([0u8; 64]).is_ok());
+    }
+
+    #[test]
+    fn take_into() {
+        use std::mem::MaybeUninit;
+
+        let alloc = Scoped::new(256).unwrap();
+        let val = alloc.allocate([7u64; 32]).unwrap();
+        let mut dst = MaybeUninit::uninit();
+        val.take_into(&mut dst);
+        assert_eq!(unsafe { dst.assume_init() }[31], 7);
+        // the block was freed.
+        assert!(alloc.allocate([0u64; 32]).is_ok());
+    }
+
+    #[test]
+    fn allocated_footprint() {
+        let val = HEAP.allocate([0u16; 5]).unwrap();
+        assert_eq!(val.allocated_size(), 10);
+        assert_eq!(val.allocated_align(), 2);
+    }
+
+    #[test]
+    fn fallible_placement() {
+        let alloc = Scoped::new(8).unwrap();
+
+        let failed: Result<AllocBox<u64, _>, &str> =
+            alloc.make_place().unwrap().try_finalize(|_| Err("failed"));
+        assert_eq!(failed.err(), Some("failed"));
+
+        // the place was freed, so there's room for another.
+        let val = alloc.make_place()
+                       .unwrap()
+                       .try_finalize(|ptr| {
+                           unsafe { ptr::write(ptr, 5u64) };
+                           Ok::<(), ()>(())
+                       })
+                       .unwrap();
+        assert_eq!(*val, 5);
+    }
+
+    #[test]
+    fn allocate_dyn_as() {
+        use std::fmt::Debug;
+
+        let val: AllocBox<Debug, _> = HEAP.allocate_dyn_as(5u8).ok().unwrap();
+        assert_eq!(format!("{:?}", &*val), "5");
+    }
+
+    #[test]
+    fn unsize() {
+        use std::fmt::Display;
+
+        let mut values: Vec<AllocBox<Display, _>> = Vec::new();
+        values.push(HEAP.allocate(1u8).ok().unwrap().unsize());
+        values.push(HEAP.allocate("two").ok().unwrap().unsize());
+        let strings: Vec<_> = values.iter().map(|val| val.to_string()).collect();
+        assert_eq!(strings, ["1", "two"]);
+
+        let slice: AllocBox<[u8], _> = HEAP.allocate([1u8, 2, 3]).ok().unwrap().unsize();
+        assert_eq!(slice.len(), 3);
+    }
+
+    #[test]
+    fn layouts() {
+        use std::alloc::Layout;
+
+        unsafe {
+            let layout = Layout::new::<[u32; 4]>();
+            let block = HEAP.allocate_layout(layout).unwrap();
+            assert_eq!(block.layout(), layout);
+
+            let bigger = Layout::from_size_align(64, 16).unwrap();
+            let block = HEAP.reallocate_layout(block, bigger).ok().unwrap();
+            assert_eq!(block.layout(), bigger);
+            assert_eq!(block.ptr() as usize % 16, 0);
+            HEAP.deallocate_layout(block.ptr(), bigger);
+        }
+    }
+
+    #[test]
+    fn allocate_with_block() {
+        let alloc = Scoped::new(64).unwrap();
+        let (val, block) = alloc.allocate_with_block([3u32; 4]).ok().unwrap();
+        assert_eq!(block.ptr(), &*val as *const _ as *mut u8);
+        assert_eq!((block.size(), block.align()), (16, 4));
+        assert!(alloc.owns_block(&block));
+    }
+
+    #[test]
+    fn reallocate_zeroed() {
+        unsafe {
+            let block = HEAP.allocate_raw(8, 1).unwrap();
+            ptr::write_bytes(block.ptr(), 0xff, 8);
+            let block = HEAP.reallocate_zeroed(block, 64).ok().unwrap();
+            let bytes = ::std::slice::from_raw_parts(block.ptr(), 64);
+            assert!(bytes[..8].iter().all(|&b| b == 0xff));
+            assert!(bytes[8..].iter().all(|&b| b == 0));
+            HEAP.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    fn allocate_zeroed() {
+        // claims its memory is zeroed, whether or not it is.
+        struct Liar<'a>(&'a Scoped<'static, HeapAllocator>);
+        unsafe impl<'a> Allocator for Liar<'a> {
+            unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+                self.0.allocate_raw(size, align)
+            }
+            unsafe fn reallocate_raw<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+                self.0.reallocate_raw(block, new_size)
+            }
+            unsafe fn deallocate_raw(&self, block: Block) {
+                self.0.deallocate_raw(block)
+            }
+            fn provides_zeroed(&self) -> bool {
+                true
+            }
+        }
+
+        let scoped = Scoped::new(64).unwrap();
+        assert!(!HEAP.provides_zeroed());
+        assert!(!scoped.provides_zeroed());
+        assert!(LazyZeroed::new(64, 16).unwrap().provides_zeroed());
+        unsafe {
+            let block = scoped.allocate_raw(16, 8).unwrap();
+            ptr::write_bytes(block.ptr(), 0xff, 16);
+            scoped.deallocate_raw(block);
+            let block = scoped.allocate_zeroed(16, 8).unwrap();
+            assert!(::std::slice::from_raw_parts(block.ptr(), 16).iter().all(|&b| b == 0));
+            ptr::write_bytes(block.ptr(), 0xff, 16);
+            scoped.deallocate_raw(block);
+
+            // the memset is skipped when the allocator vouches for its memory.
+            let liar = Liar(&scoped);
+            assert!((&liar).provides_zeroed());
+            let block = liar.allocate_zeroed(16, 8).unwrap();
+            assert!(::std::slice::from_raw_parts(block.ptr(), 16).iter().all(|&b| b == 0xff));
+            liar.deallocate_raw(block);
+        }
+    }
+
+    #[test]
+    fn oversize_alignment() {
+        fn assert_rejects<A: Allocator>(alloc: &A) {
+            let too_large = Error::AlignmentTooLarge {
+                requested: MAX_ALIGN << 1,
+                max: MAX_ALIGN,
+            };
+            unsafe {
+                match alloc.allocate_raw(8, MAX_ALIGN << 1) {
+                    Ok(_) => panic!("Allocated with an alignment above MAX_ALIGN."),
+                    Err(err) => assert_eq!(err, too_large),
+                }
+                match alloc.allocate_raw(8, 24) {
+                    Ok(_) => panic!("Allocated with an alignment which isn't a power of two."),
+                    Err(err) => assert_eq!(err, Error::UnsupportedAlignment),
+                }
+            }
+        }
+
+        assert_rejects(HEAP);
+        assert_rejects(&Scoped::new(64).unwrap());
+        assert_rejects(&LazyZeroed::new(64, 16).unwrap());
+        assert_rejects(&InlineScoped::<64>::new());
+        assert_rejects(&AtomicInlineScoped::<64>::new());
+        assert_rejects(&FreeList::new(64, 1).unwrap());
+        assert_rejects(&Buckets::new(&[(16, 4), (64, 4)]).unwrap());
+
+        // within the ceiling, but more than the buffer could ever satisfy.
+        let scoped = Scoped::new(64).unwrap();
+        unsafe {
+            match scoped.allocate_raw(8, 1 << 20) {
+                Ok(_) => panic!("Allocated past the end of the buffer."),
+                Err(err) => {
+                    assert_eq!(err,
+                               Error::OutOfMemory {
+                                   requested: 8,
+                                   align: 1 << 20,
+                               })
+                }
+            }
+            assert!(scoped.allocate_raw_unchecked(8, 1 << 20).is_err());
+            assert!(scoped.allocate_back(8, MAX_ALIGN << 1).is_err());
+        }
+        assert_eq!(scoped.remaining(), Some(64));
+        scoped.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn block_debug() {
+        let block = unsafe { HEAP.allocate_raw(24, 8).unwrap() };
+        let shown = format!("{:?}", block);
+        assert!(shown.contains("size: 24"));
+        assert!(shown.contains("align: 8"));
+        assert!(shown.contains(&format!("{:?}", block.ptr())));
+        unsafe { HEAP.deallocate_raw(block) };
+        assert_eq!(format!("{:?}", Block::empty()), "Block { empty }");
+    }
+
+    #[test]
+    fn remaining_capacity() {
+        assert_eq!(HEAP.remaining(), None);
+        assert_eq!(NullAllocator.remaining(), Some(0));
+
+        let scoped = Scoped::new(64).unwrap();
+        let _val = scoped.allocate(0u64).unwrap();
+        assert_eq!(scoped.remaining(), Some(56));
+
+        let inline = InlineScoped::<16>::new();
+        let _val = inline.allocate([0u8; 10]).unwrap();
+        assert_eq!(inline.remaining(), Some(6));
+
+        let list = FreeList::new(16, 4).unwrap();
+        let _val = list.allocate(0u64).unwrap();
+        assert_eq!(list.remaining(), Some(48));
+    }
+
+    #[test]
+    fn poll_allocated_future() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        // a future which is ready on its second poll.
+        let alloc = Scoped::new(256).unwrap();
+        let mut polled = false;
+        let ready = ::std::future::poll_fn(move |cx| {
+            if polled {
+                Poll::Ready(5)
+            } else {
+                polled = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        });
+        let mut unpinned = alloc.allocate(ready).ok().unwrap();
+        // neither box is leaked.
+        let mut pinned = unsafe { alloc.allocate_pinned(::std::future::ready(7)).ok().unwrap() };
+
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(Pin::new(&mut unpinned).poll(&mut cx), Poll::Pending);
+        assert_eq!(Pin::new(&mut unpinned).poll(&mut cx), Poll::Ready(5));
+        assert_eq!(pinned.as_mut().poll(&mut cx), Poll::Ready(7));
+    }
+
+    #[test]
+    fn alignment_offset() {
+        let mut buf = [0u64; 4];
+        let ptr = buf.as_mut_ptr() as *mut u8;
+        let aligned = Block::new(ptr, 8, 8);
+        let misaligned = Block::new(unsafe { ptr.offset(3) }, 8, 1);
+        assert_eq!(aligned.alignment_offset(8), 0);
+        assert_eq!(misaligned.alignment_offset(8), 3);
+        assert_eq!(misaligned.alignment_offset(1), 0);
+    }
+
+    #[test]
+    fn aligned_slice() {
+        let alloc = Scoped::new(1024).unwrap();
+        let _offset = alloc.allocate(0u8).unwrap();
+        let slice = alloc.allocate_aligned_slice::<f32>(16, 64).unwrap();
+        assert_eq!(slice.as_ptr() as usize % 64, 0);
+        assert_eq!(slice.len(), 16);
+        assert_eq!(slice.allocated_align(), 64);
+        assert!(alloc.allocate_aligned_slice::<u64>(1, 4).is_err());
+        assert!(alloc.allocate_aligned_slice::<u8>(1, 48).is_err());
+    }
+
+    #[test]
+    fn aligned_slice_panics() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static MADE: AtomicUsize = AtomicUsize::new(0);
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        struct Fragile(usize);
+        impl Default for Fragile {
+            fn default() -> Self {
+                let made = MADE.fetch_add(1, Ordering::SeqCst);
+                if made == 3 {
+                    panic!("Out of fragile things.");
+                }
+                Fragile(made)
+            }
+        }
+        impl Drop for Fragile {
+            fn drop(&mut self) {
+                assert!(self.0 < 3);
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let alloc = Scoped::new(128).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| alloc.allocate_aligned_slice::<Fragile>(4, 16)));
+        assert!(result.is_err());
+        // the values made before the panic were dropped, and the block freed.
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 3);
+        assert_eq!(alloc.remaining(), Some(128));
+    }
+
+    #[test]
+    #[should_panic]
+    fn aligned_slice_raw_parts() {
+        let slice = HEAP.allocate_aligned_slice::<u8>(16, 64).unwrap();
+        let _ = slice.into_raw_parts();
+    }
+
     #[test]
     fn boxed_allocator() {
         #[derive(Debug)]
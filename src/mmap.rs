@@ -0,0 +1,167 @@
+//! An allocator which maps memory directly from the operating system.
+
+use std::cell::RefCell;
+
+use super::{Allocator, CapacityAware, Error, Block, BlockOwner, os};
+
+/// An allocator which maps every block directly from the operating system,
+/// using `mmap` on Unix and `VirtualAlloc` on Windows.
+///
+/// Blocks are aligned to the page size and their sizes rounded up to whole pages.
+/// The operating system only commits physical memory to pages as they are touched,
+/// so this makes a good parent for very large arenas which are only partly used.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{MmapAllocator, Scoped};
+///
+/// let mmap = MmapAllocator::new();
+/// // a gigabyte of address space, which costs nothing until it's used.
+/// let arena = Scoped::new_from(&mmap, 1 << 30).unwrap();
+/// ```
+pub struct MmapAllocator {
+    // the start and mapped length of every block handed out.
+    regions: RefCell<Vec<(usize, usize)>>,
+}
+
+impl MmapAllocator {
+    /// Creates a new `MmapAllocator`.
+    pub fn new() -> Self {
+        MmapAllocator { regions: RefCell::new(Vec::new()) }
+    }
+
+    /// The granularity blocks are mapped with.
+    pub fn page_size(&self) -> usize {
+        os::page_size()
+    }
+
+    fn round_up(&self, size: usize) -> Option<usize> {
+        let page_size = os::page_size();
+        match size.checked_add(page_size - 1) {
+            Some(size) => Some(size & !(page_size - 1)),
+            None => None,
+        }
+    }
+
+    fn mapped_len(&self, ptr: *mut u8) -> Option<usize> {
+        let regions = self.regions.borrow();
+        regions.iter().find(|&&(start, _)| start == ptr as usize).map(|&(_, len)| len)
+    }
+}
+
+unsafe impl Allocator for MmapAllocator {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        if size == 0 {
+            return Ok(Block::empty());
+        } else if align > os::page_size() {
+            return Err(Error::AlignmentTooLarge {
+                requested: align,
+                max: os::page_size(),
+            });
+        }
+
+        let len = match self.round_up(size) {
+            Some(len) => len,
+            None => return Err(Error::OutOfMemory {
+                requested: usize::MAX,
+                align: align,
+            }),
+        };
+
+        let ptr = os::map(len);
+        if ptr.is_null() {
+            Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            })
+        } else {
+            self.regions.borrow_mut().push((ptr as usize, len));
+            Ok(Block::new(ptr, size, align))
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        if new_size == 0 {
+            self.deallocate_raw(block);
+            Ok(Block::empty())
+        } else if block.is_empty() {
+            Err((Error::UnsupportedAlignment, block))
+        } else if self.mapped_len(block.ptr()).map_or(false, |len| new_size <= len) {
+            // the new size still fits in the pages already mapped.
+            Ok(Block::new(block.ptr(), new_size, block.align()))
+        } else {
+            let align = block.align();
+            super::copy_reallocate(self, block, new_size, align)
+        }
+    }
+
+    /// Every block is freshly mapped, so it reads as zero.
+    fn provides_zeroed(&self) -> bool {
+        true
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if block.is_empty() {
+            return;
+        }
+
+        let mut regions = self.regions.borrow_mut();
+        if let Some(idx) = regions.iter().position(|&(start, _)| start == block.ptr() as usize) {
+            let (start, len) = regions.swap_remove(idx);
+            os::unmap(start as *mut u8, len);
+        }
+    }
+}
+
+impl CapacityAware for MmapAllocator {
+    fn remaining(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl BlockOwner for MmapAllocator {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        let ptr = ptr as usize;
+        self.regions.borrow().iter().any(|&(start, len)| ptr >= start && ptr < start + len)
+    }
+
+    /// Unmaps every block.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        for (start, len) in self.regions.borrow_mut().drain(..) {
+            os::unmap(start as *mut u8, len);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MmapAllocator {
+    fn drop(&mut self) {
+        for &(start, len) in self.regions.borrow().iter() {
+            unsafe { os::unmap(start as *mut u8, len) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn large_sparse_arena() {
+        let mmap = MmapAllocator::new();
+        let size = 1 << 28;
+        let arena = Scoped::new_from(&mmap, size).unwrap();
+        let block = unsafe { arena.allocate_raw(size, 1).unwrap() };
+        assert!(mmap.owns_block(&block));
+        assert_eq!(block.ptr() as usize % mmap.page_size(), 0);
+
+        // only the pages written to get committed.
+        for offset in (0..size).step_by(1 << 24) {
+            unsafe { *block.ptr().offset(offset as isize) = 1 };
+        }
+        assert_eq!(unsafe { *block.ptr().offset(1 << 24) }, 1);
+    }
+}
@@ -0,0 +1,93 @@
+// Thin wrappers over the operating system's virtual memory facilities.
+
+#[cfg(unix)]
+use libc;
+
+#[cfg(windows)]
+mod windows {
+    pub const MEM_COMMIT: u32 = 0x1000;
+    pub const MEM_RESERVE: u32 = 0x2000;
+    pub const MEM_RESET: u32 = 0x80000;
+    pub const MEM_RELEASE: u32 = 0x8000;
+    pub const PAGE_READWRITE: u32 = 0x04;
+
+    extern "system" {
+        pub fn VirtualAlloc(addr: *mut u8, size: usize, ty: u32, protect: u32) -> *mut u8;
+        pub fn VirtualFree(addr: *mut u8, size: usize, ty: u32) -> i32;
+    }
+}
+
+// the size of a page of virtual memory.
+#[cfg(unix)]
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(not(unix))]
+pub fn page_size() -> usize {
+    4096
+}
+
+// maps a fresh, zeroed, page-aligned region of `len` bytes, returning null on failure.
+// physical memory is only committed to it as its pages are touched.
+#[cfg(unix)]
+pub unsafe fn map(len: usize) -> *mut u8 {
+    let ptr = libc::mmap(::std::ptr::null_mut(),
+                         len,
+                         libc::PROT_READ | libc::PROT_WRITE,
+                         libc::MAP_PRIVATE | libc::MAP_ANON,
+                         -1,
+                         0);
+    if ptr == libc::MAP_FAILED {
+        ::std::ptr::null_mut()
+    } else {
+        ptr as *mut u8
+    }
+}
+
+#[cfg(windows)]
+pub unsafe fn map(len: usize) -> *mut u8 {
+    windows::VirtualAlloc(::std::ptr::null_mut(),
+                          len,
+                          windows::MEM_RESERVE | windows::MEM_COMMIT,
+                          windows::PAGE_READWRITE)
+}
+
+// unmaps a region returned by `map`.
+#[cfg(unix)]
+pub unsafe fn unmap(ptr: *mut u8, len: usize) {
+    libc::munmap(ptr as *mut libc::c_void, len);
+}
+
+#[cfg(windows)]
+pub unsafe fn unmap(ptr: *mut u8, _len: usize) {
+    windows::VirtualFree(ptr, 0, windows::MEM_RELEASE);
+}
+
+#[cfg(not(any(unix, windows)))]
+pub unsafe fn map(_len: usize) -> *mut u8 {
+    ::std::ptr::null_mut()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub unsafe fn unmap(_ptr: *mut u8, _len: usize) {}
+
+// advises the OS that the pages in the region are unused, so it may release
+// the physical memory backing them. The memory stays valid to use, but its
+// contents are lost. The region must be page-aligned.
+// on Linux, `MADV_DONTNEED` makes private anonymous pages read as zeroes afterwards.
+// macOS and the BSDs only treat it as a hint, so the old contents may remain.
+#[cfg(unix)]
+pub unsafe fn decommit(ptr: *mut u8, len: usize) {
+    libc::madvise(ptr as *mut libc::c_void, len, libc::MADV_DONTNEED);
+}
+
+// `MEM_DECOMMIT` would make the pages inaccessible until recommitted, so
+// `MEM_RESET` is used instead: the pages stay committed, but their contents are discarded.
+#[cfg(windows)]
+pub unsafe fn decommit(ptr: *mut u8, len: usize) {
+    windows::VirtualAlloc(ptr, len, windows::MEM_RESET, windows::PAGE_READWRITE);
+}
+
+#[cfg(not(any(unix, windows)))]
+pub unsafe fn decommit(_ptr: *mut u8, _len: usize) {}
@@ -0,0 +1,353 @@
+//! Pools of reusable objects.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Allocator, AllocBox, Error, HeapAllocator, Reset, Scoped, HEAP};
+
+/// A pool of reusable objects of type `T`, whose storage is drawn from an allocator.
+///
+/// Objects are created on demand and handed out behind a `Pooled` guard.
+/// When the guard is dropped, the object goes back into the pool instead of
+/// being freed, so the next `acquire` reuses it without touching the allocator.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{FreeList, ObjectPool};
+///
+/// // a free list with room for 8 vectors.
+/// let alloc = FreeList::new(64, 8).unwrap();
+/// let pool = ObjectPool::new(&alloc, || Vec::with_capacity(1024))
+///                .with_reset(|buf: &mut Vec<u8>| buf.clear());
+///
+/// for _ in 0..100 {
+///     // the same scratch buffer gets handed out every time.
+///     let mut buf = pool.acquire().unwrap();
+///     buf.extend_from_slice(b"scratch space");
+/// }
+/// ```
+pub struct ObjectPool<'a, T: 'a, A: 'a + Allocator> {
+    allocator: &'a A,
+    create: Box<Fn() -> T + 'a>,
+    reset: Option<Box<Fn(&mut T) + 'a>>,
+    idle: RefCell<Vec<AllocBox<'a, T, A>>>,
+}
+
+impl<'a, T: 'a, A: 'a + Allocator> ObjectPool<'a, T, A> {
+    /// Creates a new, empty pool which allocates from `alloc`
+    /// and creates new objects with `create`.
+    pub fn new<F>(alloc: &'a A, create: F) -> Self
+        where F: Fn() -> T + 'a
+    {
+        ObjectPool {
+            allocator: alloc,
+            create: Box::new(create),
+            reset: None,
+            idle: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Sets a function to be called on every object as it is returned to the pool.
+    pub fn with_reset<F>(mut self, reset: F) -> Self
+        where F: Fn(&mut T) + 'a
+    {
+        self.reset = Some(Box::new(reset));
+        self
+    }
+
+    /// Takes an object from the pool, creating a new one if none are idle.
+    ///
+    /// Returns `None` if a new object was needed but the allocator
+    /// could not make room for it.
+    pub fn acquire<'p>(&'p self) -> Option<Pooled<'p, 'a, T, A>> {
+        let idle = self.idle.borrow_mut().pop();
+        let item = match idle {
+            Some(item) => item,
+            None => {
+                match self.allocator.allocate((self.create)()) {
+                    Ok(item) => item,
+                    Err(_) => return None,
+                }
+            }
+        };
+
+        Some(Pooled {
+            pool: self,
+            item: Some(item),
+        })
+    }
+
+    /// The number of objects currently waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+}
+
+/// An object on loan from an `ObjectPool`.
+/// It is returned to the pool when dropped.
+pub struct Pooled<'p, 'a: 'p, T: 'a, A: 'a + Allocator> {
+    pool: &'p ObjectPool<'a, T, A>,
+    item: Option<AllocBox<'a, T, A>>,
+}
+
+impl<'p, 'a, T, A: Allocator> Deref for Pooled<'p, 'a, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().unwrap()
+    }
+}
+
+impl<'p, 'a, T, A: Allocator> DerefMut for Pooled<'p, 'a, T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().unwrap()
+    }
+}
+
+impl<'p, 'a, T, A: Allocator> Drop for Pooled<'p, 'a, T, A> {
+    fn drop(&mut self) {
+        if let Some(mut item) = self.item.take() {
+            if let Some(ref reset) = self.pool.reset {
+                reset(&mut item);
+            }
+            self.pool.idle.borrow_mut().push(item);
+        }
+    }
+}
+
+thread_local! {
+    // the arenas of every `ThreadLocalPool` this thread has used, by pool id.
+    static ARENAS: RefCell<HashMap<usize, Box<Any>>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+// a thread's arena, along with the number of calls to `with_arena` using it.
+struct ArenaSlot<A: 'static + Allocator> {
+    arena: Scoped<'static, A>,
+    users: Cell<usize>,
+}
+
+// marks an arena as no longer used by a call to `with_arena`, even if it unwinds.
+struct InUse<'a>(&'a Cell<usize>);
+
+impl<'a> Drop for InUse<'a> {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+/// A pool of `Scoped` arenas, one per thread, created lazily on first use.
+///
+/// Each thread allocates from its own arena, so no synchronization is needed.
+/// This is the usual pattern for request-scoped allocations in servers: each worker
+/// thread allocates freely while handling a request, then resets its arena
+/// once it's done. A thread's arena lives until the thread exits, or until the
+/// pool is dropped on that thread.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Allocator, ThreadLocalPool};
+///
+/// let pool = ThreadLocalPool::new(4096);
+/// let sum = pool.with_arena(|arena| {
+///     let vals = arena.collect_in(0..10u32).unwrap();
+///     vals.iter().sum::<u32>()
+/// }).unwrap();
+/// assert_eq!(sum, 45);
+/// pool.reset();
+/// ```
+pub struct ThreadLocalPool<A: 'static + Allocator + Sync> {
+    id: usize,
+    parent: &'static A,
+    arena_size: usize,
+}
+
+impl ThreadLocalPool<HeapAllocator> {
+    /// Creates a new pool whose arenas are each `arena_size` bytes from the heap.
+    pub fn new(arena_size: usize) -> Self {
+        ThreadLocalPool::new_from(HEAP, arena_size)
+    }
+}
+
+impl<A: 'static + Allocator + Sync> ThreadLocalPool<A> {
+    /// Creates a new pool whose arenas are each `arena_size` bytes from the allocator supplied.
+    pub fn new_from(alloc: &'static A, arena_size: usize) -> Self {
+        ThreadLocalPool {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            parent: alloc,
+            arena_size: arena_size,
+        }
+    }
+
+    /// The size of each thread's arena.
+    pub fn arena_size(&self) -> usize {
+        self.arena_size
+    }
+
+    /// Calls the supplied function with the calling thread's arena, creating it if
+    /// this is the first time the thread uses the pool.
+    ///
+    /// Returns an error if the arena couldn't be created.
+    pub fn with_arena<F, U>(&self, f: F) -> Result<U, Error>
+        where F: FnOnce(&Scoped<'static, A>) -> U
+    {
+        let slot = match self.slot() {
+            Ok(slot) => slot,
+            Err(err) => return Err(err),
+        };
+
+        // the slot is boxed, so it stays put even if the map is modified by `f`, and it's
+        // only removed when this pool is dropped, which can't happen while it's borrowed.
+        let slot = unsafe { &*slot };
+        slot.users.set(slot.users.get() + 1);
+        let _in_use = InUse(&slot.users);
+        Ok(f(&slot.arena))
+    }
+
+    /// Resets the calling thread's arena, reclaiming everything allocated from it.
+    ///
+    /// Returns false without doing anything if the arena is in use by a call to
+    /// `with_arena` further up the stack, or if the thread has no arena yet.
+    pub fn reset(&self) -> bool {
+        let slot = match self.find() {
+            Some(slot) => unsafe { &*slot },
+            None => return false,
+        };
+
+        if slot.users.get() != 0 {
+            return false;
+        }
+        // nothing allocated from the arena can outlive the calls to `with_arena`.
+        unsafe { slot.arena.reset() };
+        true
+    }
+
+    fn find(&self) -> Option<*const ArenaSlot<A>> {
+        ARENAS.with(|arenas| {
+            arenas.borrow()
+                  .get(&self.id)
+                  .map(|slot| slot.downcast_ref::<ArenaSlot<A>>().unwrap() as *const ArenaSlot<A>)
+        })
+    }
+
+    fn slot(&self) -> Result<*const ArenaSlot<A>, Error> {
+        if let Some(slot) = self.find() {
+            return Ok(slot);
+        }
+
+        let arena = match Scoped::new_from(self.parent, self.arena_size) {
+            Ok(arena) => arena,
+            Err(err) => return Err(err),
+        };
+        let slot: Box<Any> = Box::new(ArenaSlot {
+            arena: arena,
+            users: Cell::new(0),
+        });
+        let ptr = slot.downcast_ref::<ArenaSlot<A>>().unwrap() as *const ArenaSlot<A>;
+        ARENAS.with(|arenas| arenas.borrow_mut().insert(self.id, slot));
+        Ok(ptr)
+    }
+}
+
+impl<A: 'static + Allocator + Sync> Drop for ThreadLocalPool<A> {
+    /// Frees the arena of the thread dropping the pool. Those of other threads
+    /// are freed as the threads exit.
+    fn drop(&mut self) {
+        let slot = ARENAS.with(|arenas| arenas.borrow_mut().remove(&self.id));
+        drop(slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn reuses_objects() {
+        let alloc = FreeList::new(64, 2).unwrap();
+        let pool = ObjectPool::new(&alloc, || Vec::<u8>::with_capacity(16));
+
+        let first = {
+            let buf = pool.acquire().unwrap();
+            &*buf as *const Vec<u8>
+        };
+        assert_eq!(pool.idle(), 1);
+
+        let buf = pool.acquire().unwrap();
+        assert_eq!(&*buf as *const Vec<u8>, first);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn resets_on_return() {
+        let pool = ObjectPool::new(HEAP, || Vec::new()).with_reset(|buf: &mut Vec<u8>| buf.clear());
+        {
+            let mut buf = pool.acquire().unwrap();
+            buf.push(1);
+        }
+        assert!(pool.acquire().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exhausted_allocator() {
+        let alloc = FreeList::new(64, 1).unwrap();
+        let pool = ObjectPool::new(&alloc, || 0u64);
+        let _first = pool.acquire().unwrap();
+        assert!(pool.acquire().is_none());
+    }
+
+    #[test]
+    fn thread_local_arenas() {
+        use std::sync::Barrier;
+        use std::thread;
+
+        let pool = ThreadLocalPool::new(256);
+        // keeps every thread, and so its arena, alive until all have recorded theirs.
+        let barrier = Barrier::new(4);
+        let here = pool.with_arena(|arena| arena as *const _ as usize).unwrap();
+        assert_eq!(pool.with_arena(|arena| arena as *const _ as usize).unwrap(), here);
+
+        let mut arenas: Vec<usize> = thread::scope(|threads| {
+            let handles: Vec<_> = (0..4)
+                .map(|i| {
+                    let (pool, barrier) = (&pool, &barrier);
+                    threads.spawn(move || {
+                        let arena = pool.with_arena(|arena| {
+                                let val = arena.allocate(i as u64).unwrap();
+                                assert_eq!(*val, i as u64);
+                                assert!(arena.owns_block(&unsafe { val.as_block() }));
+                                arena as *const _ as usize
+                            })
+                            .unwrap();
+                        barrier.wait();
+                        arena
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        arenas.push(here);
+        arenas.sort();
+        arenas.dedup();
+        assert_eq!(arenas.len(), 5);
+    }
+
+    #[test]
+    fn thread_local_reset() {
+        let pool = ThreadLocalPool::new(64);
+        assert!(!pool.reset());
+        pool.with_arena(|arena| {
+                ::std::mem::forget(arena.allocate([0u8; 32]).unwrap());
+                // can't reset an arena while it's in use.
+                assert!(!pool.reset());
+            })
+            .unwrap();
+        assert_eq!(pool.with_arena(|arena| arena.remaining()).unwrap(), Some(32));
+        assert!(pool.reset());
+        assert_eq!(pool.with_arena(|arena| arena.remaining()).unwrap(), Some(64));
+    }
+}
@@ -0,0 +1,133 @@
+//! Reference-counted pointers backed by a custom allocator.
+
+use std::cell::Cell;
+use std::mem;
+use std::ops::Deref;
+use std::ptr;
+
+use super::{Allocator, Block, Error};
+
+// the count and the value share a single block.
+struct RcBox<T> {
+    count: Cell<usize>,
+    value: T,
+}
+
+/// A single-threaded reference-counted pointer, whose value and count
+/// are stored together in one block drawn from a custom allocator.
+///
+/// The block is freed through the allocator when the last pointer is dropped.
+pub struct AllocRc<'a, T, A: 'a + ?Sized + Allocator> {
+    ptr: *mut RcBox<T>,
+    allocator: &'a A,
+}
+
+impl<'a, T, A: ?Sized + Allocator> AllocRc<'a, T, A> {
+    /// Moves a value into a new block from the allocator.
+    ///
+    /// On failure, the value is given back along with the error.
+    pub fn new(val: T, alloc: &'a A) -> Result<Self, (Error, T)> {
+        let (size, align) = (mem::size_of::<RcBox<T>>(), mem::align_of::<RcBox<T>>());
+        match unsafe { alloc.allocate_raw(size, align) } {
+            Ok(block) => {
+                let ptr = if block.is_empty() {
+                    align as *mut RcBox<T>
+                } else {
+                    block.ptr() as *mut RcBox<T>
+                };
+                unsafe {
+                    ptr::write(ptr,
+                               RcBox {
+                                   count: Cell::new(1),
+                                   value: val,
+                               })
+                };
+
+                Ok(AllocRc {
+                    ptr: ptr,
+                    allocator: alloc,
+                })
+            }
+            Err(err) => Err((err, val)),
+        }
+    }
+
+    /// The number of pointers to this value.
+    pub fn count(this: &Self) -> usize {
+        this.inner().count.get()
+    }
+
+    /// Whether two pointers point to the same value.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    fn inner(&self) -> &RcBox<T> {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Clone for AllocRc<'a, T, A> {
+    fn clone(&self) -> Self {
+        let count = &self.inner().count;
+        count.set(count.get() + 1);
+        AllocRc {
+            ptr: self.ptr,
+            allocator: self.allocator,
+        }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Deref for AllocRc<'a, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Drop for AllocRc<'a, T, A> {
+    fn drop(&mut self) {
+        let count = self.inner().count.get() - 1;
+        self.inner().count.set(count);
+        if count == 0 {
+            let size = mem::size_of::<RcBox<T>>();
+            unsafe {
+                ptr::drop_in_place(self.ptr);
+                if size != 0 {
+                    let block = Block::new(self.ptr as *mut u8, size, mem::align_of::<RcBox<T>>());
+                    self.allocator.deallocate_raw(block);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn shared_ownership() {
+        let alloc = Scoped::new(64).unwrap();
+        let first = AllocRc::new(vec![1, 2, 3], &alloc).ok().unwrap();
+        let second = first.clone();
+        assert!(AllocRc::ptr_eq(&first, &second));
+        assert_eq!(AllocRc::count(&first), 2);
+
+        drop(first);
+        assert_eq!(AllocRc::count(&second), 1);
+        assert_eq!(*second, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn frees_on_last_drop() {
+        let alloc = Scoped::new(16).unwrap();
+        let rc = AllocRc::new(0u64, &alloc).ok().unwrap();
+        let clone = rc.clone();
+        drop(rc);
+        assert!(alloc.allocate(0u64).is_err());
+        drop(clone);
+        assert!(alloc.allocate(0u64).is_ok());
+    }
+}
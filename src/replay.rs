@@ -0,0 +1,161 @@
+//! Replays sequences of allocator operations, checking the allocator's
+//! bookkeeping after every step.
+//!
+//! `replay` treats every index in the operations it's given as valid, so it can be
+//! driven directly by arbitrary input from a fuzzer or property testing library.
+//! This module is only available with the `replay` feature.
+
+use std::slice;
+
+use super::{Allocator, Block, BlockOwner, DoubleBuffer, FreeList, InlineScoped, Scoped};
+
+/// An allocator which can check its own bookkeeping for consistency.
+pub trait Invariants {
+    /// Returns a description of the first inconsistency found, if any.
+    fn check_invariants(&self) -> Result<(), String>;
+}
+
+impl<'a, A: Allocator> Invariants for FreeList<'a, A> {
+    fn check_invariants(&self) -> Result<(), String> {
+        FreeList::check_invariants(self)
+    }
+}
+
+impl<'a, A: Allocator> Invariants for Scoped<'a, A> {
+    fn check_invariants(&self) -> Result<(), String> {
+        Scoped::check_invariants(self)
+    }
+}
+
+impl<const N: usize> Invariants for InlineScoped<N> {
+    fn check_invariants(&self) -> Result<(), String> {
+        InlineScoped::check_invariants(self)
+    }
+}
+
+impl<'a, A: Allocator> Invariants for DoubleBuffer<'a, A> {
+    fn check_invariants(&self) -> Result<(), String> {
+        DoubleBuffer::check_invariants(self)
+    }
+}
+
+/// An operation to apply to an allocator.
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Allocate a block of `size` bytes aligned to `1 << align_log2`.
+    Alloc { size: usize, align_log2: u8 },
+    /// Free a live block, chosen by index modulo the number of live blocks.
+    Dealloc(usize),
+    /// Reallocate a live block, chosen by index modulo the number of live blocks,
+    /// to a new size.
+    Realloc(usize, usize),
+}
+
+/// Applies a sequence of operations to an allocator, returning a description of the
+/// first inconsistency found.
+///
+/// After every step, the allocator's own `check_invariants` must pass, and every live
+/// block must lie within the allocator, be aligned as requested, and not overlap any
+/// other. Each block is filled with a byte of its own, which must be intact when it's
+/// freed and survive reallocation, so a block handed out twice or corrupted by the
+/// allocator's bookkeeping is caught. Allocations are allowed to fail. Any blocks still
+/// live at the end are freed.
+pub fn replay<A: Allocator + BlockOwner + Invariants>(alloc: &A, ops: &[Op]) -> Result<(), String> {
+    let mut live: Vec<(Block, u8)> = Vec::new();
+    let mut next_tag = 0u8;
+    let mut result = Ok(());
+
+    for (step, &op) in ops.iter().enumerate() {
+        match op {
+            Op::Alloc { size, align_log2 } => {
+                let align = 1 << (align_log2 % 8);
+                if let Ok(block) = unsafe { alloc.allocate_raw(size, align) } {
+                    if !block.is_empty() {
+                        next_tag = next_tag.wrapping_add(1);
+                        unsafe { fill(&block, next_tag) };
+                        live.push((block, next_tag));
+                    }
+                }
+            }
+            Op::Dealloc(idx) if !live.is_empty() => {
+                let (block, tag) = live.swap_remove(idx % live.len());
+                if !unsafe { holds(&block, block.size(), tag) } {
+                    result = Err(format!("Step {}: block {:?} was overwritten before being freed.",
+                                         step,
+                                         block.ptr()));
+                }
+                unsafe { alloc.deallocate_raw(block) };
+            }
+            Op::Realloc(idx, new_size) if !live.is_empty() => {
+                let idx = idx % live.len();
+                let (block, tag) = live.swap_remove(idx);
+                let old_size = block.size();
+                match unsafe { alloc.reallocate_raw(block, new_size) } {
+                    Ok(block) => {
+                        if block.is_empty() {
+                            continue;
+                        }
+                        let kept = if old_size < new_size { old_size } else { new_size };
+                        if !unsafe { holds(&block, kept, tag) } {
+                            result = Err(format!("Step {}: reallocating to {:?} lost its contents.",
+                                                 step,
+                                                 block.ptr()));
+                        }
+                        unsafe { fill(&block, tag) };
+                        live.push((block, tag));
+                    }
+                    Err((_, block)) => live.push((block, tag)),
+                }
+            }
+            _ => {}
+        }
+
+        if result.is_ok() {
+            result = match alloc.check_invariants() {
+                Ok(()) => check(alloc, &live),
+                Err(err) => Err(err),
+            };
+            result = result.map_err(|err| format!("Step {}: {}", step, err));
+        }
+        if result.is_err() {
+            break;
+        }
+    }
+
+    for (block, _) in live {
+        unsafe { alloc.deallocate_raw(block) };
+    }
+    result
+}
+
+// checks that every live block is owned, aligned, and disjoint from the others.
+fn check<A: BlockOwner>(alloc: &A, live: &[(Block, u8)]) -> Result<(), String> {
+    let mut spans: Vec<(usize, usize)> = Vec::with_capacity(live.len());
+    for &(ref block, _) in live {
+        if !alloc.owns_block(block) {
+            return Err(format!("Block {:?} lies outside of the allocator.", block.ptr()));
+        }
+        if block.ptr() as usize % block.align() != 0 {
+            return Err(format!("Block {:?} isn't aligned to {}.", block.ptr(), block.align()));
+        }
+        spans.push((block.ptr() as usize, block.ptr() as usize + block.size()));
+    }
+
+    spans.sort();
+    for pair in spans.windows(2) {
+        if pair[0].1 > pair[1].0 {
+            return Err(format!("Blocks at {:#x} and {:#x} overlap.", pair[0].0, pair[1].0));
+        }
+    }
+    Ok(())
+}
+
+unsafe fn fill(block: &Block, tag: u8) {
+    for byte in slice::from_raw_parts_mut(block.ptr(), block.size()) {
+        *byte = tag;
+    }
+}
+
+unsafe fn holds(block: &Block, len: usize, tag: u8) -> bool {
+    slice::from_raw_parts(block.ptr(), len).iter().all(|&byte| byte == tag)
+}
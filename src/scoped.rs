@@ -1,19 +1,33 @@
 //! A scoped linear allocator. This is something of a cross between a stack allocator
 //! and a traditional linear allocator.
 
-use std::cell::Cell;
-use std::mem;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::cmp;
+use std::mem::{self, MaybeUninit};
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use super::{Allocator, Error, Block, BlockOwner, HeapAllocator, HEAP};
+use super::{AllocBox, Allocator, CapacityAware, Error, Block, BlockOwner, HeapAllocator, Report, Reset, HEAP};
 
 /// A scoped linear allocator.
 pub struct Scoped<'parent, A: 'parent + Allocator> {
     allocator: &'parent A,
+    // the allocator this is a scope of, or null if it isn't one.
+    parent: *const Scoped<'parent, A>,
     current: Cell<*mut u8>,
+    // the start of the lowest block allocated from the back.
+    back: Cell<*mut u8>,
     end: *mut u8,
     root: bool,
     start: *mut u8,
+    allocations: Cell<usize>,
+    failures: Cell<usize>,
+    padding: Cell<usize>,
+    markers: RefCell<Vec<*mut u8>>,
+    reserve: usize,
+    scope_align: usize,
+    // callbacks to run at the next reset.
+    finalizers: RefCell<Vec<Box<FnOnce() + Send + 'parent>>>,
 }
 
 impl Scoped<'static, HeapAllocator> {
@@ -21,6 +35,16 @@ impl Scoped<'static, HeapAllocator> {
     pub fn new(size: usize) -> Result<Self, Error> {
         Scoped::new_from(HEAP, size)
     }
+
+    /// Creates a new `Scoped` backed by `size` bytes from the heap, the last `reserve`
+    /// of which can only be allocated with `allocate_reserved`.
+    ///
+    /// This keeps some headroom for critical allocations, like those on an error
+    /// handling path, which ordinary allocations can't eat into.
+    /// Fails if the reserve is larger than the buffer.
+    pub fn with_reserve(size: usize, reserve: usize) -> Result<Self, Error> {
+        Scoped::with_reserve_from(HEAP, size, reserve)
+    }
 }
 
 impl<'parent, A: Allocator> Scoped<'parent, A> {
@@ -30,21 +54,83 @@ impl<'parent, A: Allocator> Scoped<'parent, A> {
         match unsafe { alloc.allocate_raw(size, mem::align_of::<usize>()) } {
             Ok(block) => Ok(Scoped {
                 allocator: alloc,
+                parent: ptr::null(),
                 current: Cell::new(block.ptr()),
+                back: Cell::new(unsafe { block.ptr().offset(block.size() as isize) }),
                 end: unsafe { block.ptr().offset(block.size() as isize) },
                 root: true,
                 start: block.ptr(),
+                allocations: Cell::new(0),
+                failures: Cell::new(0),
+                padding: Cell::new(0),
+                markers: RefCell::new(Vec::new()),
+                reserve: 0,
+                scope_align: 1,
+                finalizers: RefCell::new(Vec::new()),
             }),
             Err(err) => Err(err),
         }
     }
 
+    /// Creates a new `Scoped` backed by `size` bytes from the allocator supplied, the last
+    /// `reserve` of which can only be allocated with `allocate_reserved`.
+    ///
+    /// Fails if the reserve is larger than the buffer.
+    pub fn with_reserve_from(alloc: &'parent A, size: usize, reserve: usize) -> Result<Self, Error> {
+        if reserve > size {
+            return Err(Error::AllocatorSpecific("Reserve larger than the buffer.".into()));
+        }
+        match Scoped::new_from(alloc, size) {
+            Ok(mut scoped) => {
+                scoped.reserve = reserve;
+                Ok(scoped)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Makes every scope entered from now on, including inner scopes, begin at
+    /// a multiple of `align`, e.g. a cache line.
+    ///
+    /// This keeps the first allocations of a scope from sharing a cache line with the
+    /// last ones before it, avoiding false sharing between them. The bytes skipped
+    /// count towards `padding_bytes`.
+    ///
+    /// # Panics
+    /// Panics if `align` isn't a power of two.
+    pub fn with_scope_alignment(mut self, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "Scope alignment must be a power of two.");
+        self.scope_align = align;
+        self
+    }
+
     /// Calls the supplied function with a new scope of the allocator.
     ///
     /// Returns the result of the closure or an error if this allocator
-    /// has already been scoped.
+    /// has already been scoped. If the closure panics, the scope ends as it unwinds,
+    /// reclaiming everything allocated in it.
     pub fn scope<F, U>(&self, f: F) -> Result<U, ()>
         where F: FnMut(&Self) -> U
+    {
+        self.enter_scope(f, false)
+    }
+
+    /// Calls the supplied function with a new scope of the allocator, like `scope`,
+    /// but keeps everything allocated within the scope afterwards.
+    ///
+    /// Unlike `scope`, this doesn't reclaim any memory when the scope ends: the
+    /// allocations remain part of this allocator until it is reset or dropped.
+    /// This is useful when a scope only serves to structure code. Values boxed within
+    /// the scope still can't outlive it, but memory handed out through raw pointers
+    /// (e.g. with `mem::forget`) stays valid.
+    pub fn scope_retaining<F, U>(&self, f: F) -> Result<U, ()>
+        where F: FnMut(&Self) -> U
+    {
+        self.enter_scope(f, true)
+    }
+
+    fn enter_scope<F, U>(&self, f: F, retain: bool) -> Result<U, ()>
+        where F: FnMut(&Self) -> U
     {
         if self.is_scoped() {
             return Err(());
@@ -52,52 +138,493 @@ impl<'parent, A: Allocator> Scoped<'parent, A> {
 
         let mut f = f;
         let old = self.current.get();
+        let begin = cmp::min(super::align_forward(old, self.scope_align), self.back.get());
+        // the inner scope can't reach the blocks allocated from the back.
         let alloc = Scoped {
             allocator: self.allocator,
-            current: self.current.clone(),
-            end: self.end,
+            parent: self,
+            current: Cell::new(begin),
+            back: self.back.clone(),
+            end: self.back.get(),
             root: false,
-            start: old,
+            start: begin,
+            allocations: Cell::new(0),
+            failures: Cell::new(0),
+            padding: Cell::new(begin as usize - old as usize),
+            markers: RefCell::new(Vec::new()),
+            reserve: self.reserve,
+            scope_align: self.scope_align,
+            finalizers: RefCell::new(Vec::new()),
         };
 
         // set the current pointer to null as a flag to indicate
         // that this allocator is being scoped.
         self.current.set(ptr::null_mut());
+        // if the closure panics, leave this unscoped again on the way out.
+        let exit = ScopeExit {
+            outer: self,
+            inner: &alloc,
+            old: old,
+        };
         let u = f(&alloc);
-        self.current.set(old);
+        mem::forget(exit);
+        self.current.set(if retain { alloc.current.get() } else { old });
+        if retain {
+            self.back.set(alloc.back.get());
+        }
+        let finalizers = mem::replace(&mut *alloc.finalizers.borrow_mut(), Vec::new());
+        if retain {
+            self.finalizers.borrow_mut().extend(finalizers);
+        } else {
+            for finalizer in finalizers {
+                finalizer();
+            }
+        }
+        self.allocations.set(self.allocations.get() + alloc.allocations.get());
+        self.failures.set(self.failures.get() + alloc.failures.get());
+        self.padding.set(self.padding.get() + alloc.padding.get());
 
         mem::forget(alloc);
         Ok(u)
     }
 
+    /// The number of bytes at the end of the buffer kept back for `allocate_reserved`.
+    pub fn reserve(&self) -> usize {
+        self.reserve
+    }
+
+    /// Allocates a block like `allocate_raw`, but may also use the reserved bytes.
+    pub unsafe fn allocate_reserved(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let result = self.bump(size, align, self.back.get());
+        match result {
+            Ok(_) => self.allocations.set(self.allocations.get() + 1),
+            Err(_) => self.failures.set(self.failures.get() + 1),
+        }
+        result
+    }
+
+    /// Allocates a block from the front of the buffer. This is the same as `allocate_raw`.
+    ///
+    /// Along with `allocate_back`, this makes the allocator a double-ended stack:
+    /// blocks allocated from the front grow upwards from the start of the buffer, and
+    /// those allocated from the back grow downwards from its end. This keeps
+    /// long-lived and short-lived data from interfering with each other.
+    /// Allocation fails when the two would cross.
+    pub unsafe fn allocate_front(&self, size: usize, align: usize) -> Result<Block, Error> {
+        self.allocate_raw(size, align)
+    }
+
+    /// Allocates a block from the back of the buffer. See `allocate_front`.
+    ///
+    /// Blocks allocated from the back are never resized in place, and are only
+    /// reclaimed by deallocating the most recent one, by a reset, or when an enclosing
+    /// scope ends. Like ordinary allocations, these leave the reserve untouched.
+    pub unsafe fn allocate_back(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        let result = if self.is_scoped() {
+            Err(Error::AllocatorSpecific("Called allocate on already scoped allocator.".into()))
+        } else {
+            self.bump_back(size, align)
+        };
+        match result {
+            Ok(_) => self.allocations.set(self.allocations.get() + 1),
+            Err(_) => self.failures.set(self.failures.get() + 1),
+        }
+        result
+    }
+
+    /// The size of the largest block aligned to `align` which can be allocated right now.
+    ///
+    /// This is `remaining` less the padding needed to align the next allocation,
+    /// so it's exactly how much an allocation with that alignment can take.
+    /// Nothing can be allocated while the allocator is scoped, or with an alignment which
+    /// isn't a power of two or is above `MAX_ALIGN`, so this is 0 then.
+    pub fn remaining_for_align(&self, align: usize) -> usize {
+        if self.is_scoped() || super::check_align(align).is_err() {
+            return 0;
+        }
+        let aligned = super::align_forward(self.current.get(), align);
+        (self.limit() as usize).saturating_sub(aligned as usize)
+    }
+
+    /// Registers a callback to run at the next `reset`, just before the memory is reclaimed.
+    ///
+    /// This lets subsystems which cache state derived from the arena invalidate it when
+    /// the arena is cleared. Callbacks run once each, in the order they were registered.
+    /// Those registered in an inner scope run when it ends, even by unwinding, or carry
+    /// over to the enclosing allocator if it's retained. Any still registered when the
+    /// allocator is dropped are discarded without running.
+    pub fn on_reset<F: FnOnce() + Send + 'parent>(&self, f: F) {
+        self.finalizers.borrow_mut().push(Box::new(f));
+    }
+
+    /// Divides the memory left in this allocator into `n` equal, non-overlapping
+    /// sub-arenas, each of which allocates independently of the others.
+    ///
+    /// Since the sub-arenas share nothing, each can be sent to a different thread and
+    /// allocated from without any synchronization. They borrow this allocator, which
+    /// keeps owning the buffer: dropping them frees nothing, and once they're all gone,
+    /// this can be used again, with everything allocated from them reclaimed.
+    /// Each sub-arena starts aligned to a `usize`, so some bytes may go unused.
+    /// The reserve is left out of the split.
+    ///
+    /// # Panics
+    /// Panics if `n` is zero.
+    pub fn split_n<'a>(&'a mut self, n: usize) -> Vec<Scoped<'a, A>> {
+        assert!(n != 0, "Can't split an allocator into zero parts.");
+        let align = mem::align_of::<usize>();
+        let begin = cmp::min(super::align_forward(self.current.get(), align), self.limit());
+        let len = ((self.limit() as usize - begin as usize) / n) & !(align - 1);
+
+        (0..n)
+            .map(|i| {
+                let start = unsafe { begin.offset((i * len) as isize) };
+                let end = unsafe { start.offset(len as isize) };
+                Scoped {
+                    allocator: self.allocator,
+                    parent: ptr::null(),
+                    current: Cell::new(start),
+                    back: Cell::new(end),
+                    end: end,
+                    root: false,
+                    start: start,
+                    allocations: Cell::new(0),
+                    failures: Cell::new(0),
+                    padding: Cell::new(0),
+                    markers: RefCell::new(Vec::new()),
+                    reserve: 0,
+                    scope_align: self.scope_align,
+                    finalizers: RefCell::new(Vec::new()),
+                }
+            })
+            .collect()
+    }
+
+    /// Whether the front and back of the allocator have met, leaving nothing
+    /// between them to allocate. Nothing can be allocated while the allocator is scoped,
+    /// so this is true then as well.
+    pub fn is_full(&self) -> bool {
+        self.is_scoped() || self.current.get() >= self.back.get()
+    }
+
+    /// The total number of bytes skipped over to align allocations so far,
+    /// including those made in inner scopes.
+    ///
+    /// This is a measure of how much capacity is lost to alignment, which can be reduced
+    /// by making allocations with the same alignment together.
+    pub fn padding_bytes(&self) -> usize {
+        self.padding.get()
+    }
+
+    /// Moves a value allocated in this scope into `outer`, the allocator this is a scope
+    /// of, so that it can outlive the scope.
+    ///
+    /// The value is copied into a new block taken from the back of this scope's memory,
+    /// costing one `memcpy` of its size, and its old memory is freed. That block then
+    /// belongs to `outer`: the result borrows `outer` rather than this scope, so it lives
+    /// on once the scope ends, until `outer` frees it or is reset. Only the most recently
+    /// promoted value is reclaimed when freed.
+    ///
+    /// Fails, giving the value back, if `outer` isn't the allocator this is a direct scope
+    /// of, if this is scoped itself, or if there isn't enough memory left.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use allocators::{Allocator, Scoped};
+    ///
+    /// let alloc = Scoped::new(64).unwrap();
+    /// let kept = alloc.scope(|inner| {
+    ///     let val = inner.allocate(5u32).unwrap();
+    ///     inner.promote(&alloc, val).ok().unwrap()
+    /// }).unwrap();
+    /// assert_eq!(*kept, 5);
+    /// ```
+    pub fn promote<'a, 'b, T>(&'b self,
+                              outer: &'a Self,
+                              val: AllocBox<'b, T, Self>)
+                              -> Result<AllocBox<'a, T, Self>, (Error, AllocBox<'b, T, Self>)> {
+        if self.parent != outer as *const Self {
+            return Err((Error::AllocatorSpecific("Can only promote into the enclosing allocator.".into()),
+                        val));
+        } else if self.is_scoped() {
+            return Err((Error::AllocatorSpecific("Called promote on already scoped allocator.".into()),
+                        val));
+        }
+
+        match unsafe { self.bump_back(mem::size_of::<T>(), mem::align_of::<T>()) } {
+            Ok(block) => {
+                // the block lies above everything this scope may allocate from now on,
+                // so moving the outer allocator's back down hands it over.
+                if !block.is_empty() {
+                    outer.back.set(block.ptr());
+                }
+                let place = unsafe { super::boxed::place_from_block(outer, block) };
+                let promoted = place.try_finalize(|ptr| {
+                    val.take_into(unsafe { &mut *(ptr as *mut MaybeUninit<T>) });
+                    Ok::<(), ()>(())
+                });
+                // moving the value in can't fail.
+                Ok(promoted.ok().unwrap())
+            }
+            Err(err) => Err((err, val)),
+        }
+    }
+
+    /// Marks the start of a new scope, to be ended by `pop_scope`.
+    ///
+    /// This is an alternative to `scope` for when scopes follow the program's
+    /// control flow at runtime, like the call frames of an interpreter.
+    /// Returns an error if this allocator is currently scoped.
+    pub fn push_scope(&self) -> Result<(), ()> {
+        if self.is_scoped() {
+            return Err(());
+        }
+
+        self.markers.borrow_mut().push(self.current.get());
+        Ok(())
+    }
+
+    /// Ends the scope started by the most recent `push_scope`, reclaiming
+    /// everything allocated since.
+    ///
+    /// Returns an error if there is no such scope, or if this allocator is currently scoped.
+    ///
+    /// # Safety
+    /// Nothing allocated since the matching `push_scope` may be used afterwards.
+    pub unsafe fn pop_scope(&self) -> Result<(), ()> {
+        if self.is_scoped() {
+            return Err(());
+        }
+
+        match self.markers.borrow_mut().pop() {
+            Some(marker) => {
+                self.current.set(marker);
+                Ok(())
+            }
+            None => Err(()),
+        }
+    }
+
     // Whether this allocator is currently scoped.
     pub fn is_scoped(&self) -> bool {
         self.current.get().is_null()
     }
-}
 
-unsafe impl<'a, A: Allocator> Allocator for Scoped<'a, A> {
-    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+    /// Advises the operating system that the unused tail of this allocator's buffer,
+    /// from the next allocation onwards, is not needed, so the physical memory backing
+    /// it may be released. This is useful after a `reset` of a large arena which has
+    /// only occasionally been filled up.
+    ///
+    /// Only whole pages are released. On Linux, they read as zeroes afterwards; on other
+    /// Unixes, like macOS and the BSDs, and on Windows, their contents become undefined.
+    /// Either way, they stay valid to allocate from.
+    /// This is a no-op on other platforms, or if the allocator is currently scoped.
+    #[cfg(feature = "os")]
+    pub fn decommit(&self) {
+        if self.is_scoped() {
+            return;
+        }
+
+        let page_size = super::os::page_size();
+        let first = super::align_forward(self.current.get(), page_size);
+        let last = (self.back.get() as usize & !(page_size - 1)) as *mut u8;
+        if first < last {
+            unsafe { super::os::decommit(first, last as usize - first as usize) }
+        }
+    }
+
+    /// Writes to every page of the unused part of the buffer, from the next allocation
+    /// onwards, so that the operating system commits physical memory to all of it now.
+    ///
+    /// This moves the cost of page faults up front, for code which can't afford them on
+    /// a latency-critical path later, such as an arena backed by an `MmapAllocator`.
+    /// It's the opposite of `decommit`. Memory already allocated is left untouched.
+    /// This is a no-op if the allocator is currently scoped.
+    pub fn prefault(&self) {
+        if self.is_scoped() {
+            return;
+        }
+
+        #[cfg(feature = "os")]
+        let page_size = super::os::page_size();
+        #[cfg(not(feature = "os"))]
+        let page_size = 4096;
+
+        let (mut ptr, back) = (self.current.get(), self.back.get());
+        while ptr < back {
+            unsafe {
+                ptr::write_volatile(ptr, 0);
+                ptr = super::align_forward(ptr.offset(1), page_size);
+            }
+        }
+    }
+
+    /// Publishes everything written to memory allocated from this so far,
+    /// returning an `Epoch` which marks how much had been allocated.
+    ///
+    /// Returns `None` if the allocator is currently scoped.
+    ///
+    /// See the docs of `Epoch` for the guarantees this provides.
+    pub fn epoch(&self) -> Option<Epoch> {
+        if self.is_scoped() {
+            return None;
+        }
+
+        let epoch = Epoch(AtomicUsize::new(0));
+        epoch.0.store(self.current.get() as usize - self.start as usize, Ordering::Release);
+        Some(epoch)
+    }
+
+    /// Checks that the allocator is consistent: that its current pointer lies
+    /// within its buffer, unless it is scoped.
+    #[cfg(any(test, feature = "invariants"))]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.start > self.end {
+            return Err(format!("Buffer starts at {:?}, after its end at {:?}.", self.start, self.end));
+        }
+
+        let current = self.current.get();
+        if !self.is_scoped() && (current < self.start || current > self.end) {
+            return Err(format!("Current pointer {:?} lies outside of the buffer {:?}..{:?}.",
+                               current,
+                               self.start,
+                               self.end));
+        }
+
+        let back = self.back.get();
+        if back > self.end || (!self.is_scoped() && back < current) {
+            return Err(format!("Back pointer {:?} lies outside of {:?}..{:?}.", back, current, self.end));
+        }
+        Ok(())
+    }
+
+    /// Allocates a block like `allocate_raw`, but without checking whether this
+    /// allocator is currently scoped. This saves a branch in hot allocation loops.
+    ///
+    /// # Safety
+    /// Calling this while the allocator is scoped, i.e. from within a closure passed to
+    /// `scope` on the same allocator, is undefined behavior. The alignment isn't checked
+    /// either: it must be a power of two no larger than `MAX_ALIGN`.
+    #[inline]
+    pub unsafe fn allocate_raw_unchecked(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let result = self.bump_unchecked(size, align, self.limit());
+        match result {
+            Ok(_) => self.allocations.set(self.allocations.get() + 1),
+            Err(_) => self.failures.set(self.failures.get() + 1),
+        }
+        result
+    }
+
+    // the end of the memory available to ordinary allocations.
+    fn limit(&self) -> *mut u8 {
+        (self.back.get() as usize).saturating_sub(self.reserve) as *mut u8
+    }
+
+    // bump the current pointer to allocate a block ending no later than limit.
+    unsafe fn bump(&self, size: usize, align: usize, limit: *mut u8) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
         if self.is_scoped() {
             return Err(Error::AllocatorSpecific("Called allocate on already scoped \
                                                           allocator."
                                                              .into()));
         }
 
+        self.bump_unchecked(size, align, limit)
+    }
+
+    // the alignment must already have been checked.
+    #[inline]
+    unsafe fn bump_unchecked(&self, size: usize, align: usize, limit: *mut u8) -> Result<Block, Error> {
         if size == 0 {
             return Ok(Block::empty());
         }
 
+        // work with addresses until the block is known to fit, so that no pointer
+        // past the buffer is ever made.
         let current_ptr = self.current.get();
-        let aligned_ptr = super::align_forward(current_ptr, align);
-        let end_ptr = aligned_ptr.offset(size as isize);
+        let aligned = match (current_ptr as usize).checked_add(align - 1) {
+            Some(bumped) => bumped & !(align - 1),
+            None => usize::MAX,
+        };
+        match aligned.checked_add(size) {
+            Some(end) if end <= limit as usize => {
+                let padding = aligned - current_ptr as usize;
+                let aligned_ptr = current_ptr.offset(padding as isize);
+                self.current.set(aligned_ptr.offset(size as isize));
+                self.padding.set(self.padding.get() + padding);
+                Ok(Block::new(aligned_ptr, size, align))
+            }
+            _ => Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            }),
+        }
+    }
+}
+
+impl<'parent, A: Allocator> Scoped<'parent, A> {
+    // takes a block from the back of the buffer, leaving the reserve untouched.
+    // the allocator must not be scoped.
+    unsafe fn bump_back<'a>(&self, size: usize, align: usize) -> Result<Block<'a>, Error> {
+        if size == 0 {
+            return Ok(Block::empty());
+        }
+
+        let floor = self.current.get() as usize + self.reserve;
+        match (self.back.get() as usize).checked_sub(size) {
+            Some(top) if top & !(align - 1) >= floor => {
+                let ptr = (top & !(align - 1)) as *mut u8;
+                self.back.set(ptr);
+                Ok(Block::new(ptr, size, align))
+            }
+            _ => Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            }),
+        }
+    }
 
-        if end_ptr > self.end {
-            Err(Error::OutOfMemory)
+    // where the back of the buffer lies when nothing has been allocated from it.
+    // values promoted out of a scope stay behind its parent's back.
+    fn ceiling(&self) -> *mut u8 {
+        if self.parent.is_null() {
+            self.end
         } else {
-            self.current.set(end_ptr);
-            Ok(Block::new(aligned_ptr, size, align))
+            unsafe { (*self.parent).back.get() }
+        }
+    }
+}
+
+// restores the allocator a scope was entered from if the scope unwinds,
+// discarding everything allocated in it and running the scope's `on_reset` callbacks.
+struct ScopeExit<'a, 'parent: 'a, A: 'parent + Allocator> {
+    outer: &'a Scoped<'parent, A>,
+    inner: &'a Scoped<'parent, A>,
+    old: *mut u8,
+}
+
+impl<'a, 'parent, A: Allocator> Drop for ScopeExit<'a, 'parent, A> {
+    fn drop(&mut self) {
+        self.outer.current.set(self.old);
+        let finalizers = mem::replace(&mut *self.inner.finalizers.borrow_mut(), Vec::new());
+        for finalizer in finalizers {
+            finalizer();
+        }
+    }
+}
+
+unsafe impl<'a, A: Allocator> Allocator for Scoped<'a, A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        let result = self.bump(size, align, self.limit());
+        match result {
+            Ok(_) => self.allocations.set(self.allocations.get() + 1),
+            Err(_) => self.failures.set(self.failures.get() + 1),
         }
+        result
     }
 
     /// Because of the way this allocator is designed, reallocating a block that is not 
@@ -111,29 +638,34 @@ unsafe impl<'a, A: Allocator> Allocator for Scoped<'a, A> {
             Err((Error::UnsupportedAlignment, block))
         } else if block.ptr().offset(block.size() as isize) == current_ptr {
             // if this block is the last allocated, resize it if we can.
-            // otherwise, we are out of memory.
-            let new_cur = current_ptr.offset((new_size - block.size()) as isize);
-            if new_cur < self.end {
-                self.current.set(new_cur);
+            // otherwise, we are out of memory. Shrinking reclaims the tail, and always
+            // succeeds, even for a block reaching into the reserve.
+            if new_size <= block.size() ||
+               new_size <= (self.limit() as usize).saturating_sub(block.ptr() as usize) {
+                self.current.set(block.ptr().offset(new_size as isize));
                 Ok(Block::new(block.ptr(), new_size, block.align()))
             } else {
-                Err((Error::OutOfMemory, block))
+                let align = block.align();
+                Err((Error::OutOfMemory {
+                    requested: new_size,
+                    align: align,
+                }, block))
             }
         } else {
             // try to allocate a new block at the end, and copy the old mem over.
             // this will lead to some fragmentation.
-            match self.allocate_raw(new_size, block.align()) {
-                Ok(new_block) => {
-                    ptr::copy_nonoverlapping(block.ptr(), new_block.ptr(), block.size());
-                    Ok(new_block)
-                }
-                Err(err) => {
-                    Err((err, block))
-                }
-            }
+            let align = block.align();
+            super::copy_reallocate(self, block, new_size, align)
         }
     }
 
+    /// Only the most recent allocation can grow in place.
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        !self.is_scoped() && !block.is_empty() &&
+        block.ptr().offset(block.size() as isize) == self.current.get() &&
+        new_size <= (self.limit() as usize).saturating_sub(block.ptr() as usize)
+    }
+
     unsafe fn deallocate_raw(&self, block: Block) {
         if block.is_empty() || block.ptr().is_null() {
             return;
@@ -141,18 +673,71 @@ unsafe impl<'a, A: Allocator> Allocator for Scoped<'a, A> {
         // no op for this unless this is the last allocation.
         // The memory gets reused when the scope is cleared.
         let current_ptr = self.current.get();
-        if !self.is_scoped() && block.ptr().offset(block.size() as isize) == current_ptr {
+        if self.is_scoped() {
+            return;
+        } else if block.ptr().offset(block.size() as isize) == current_ptr {
             self.current.set(block.ptr());
+        } else if block.ptr() == self.back.get() {
+            self.back.set(block.ptr().offset(block.size() as isize));
         }
     }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("Scoped");
+        if !self.is_scoped() {
+            report.bytes_outstanding = (self.current.get() as usize - self.start as usize) +
+                                       (self.end as usize - self.back.get() as usize);
+        }
+        report.allocations = self.allocations.get();
+        report.failures = self.failures.get();
+        report
+    }
 }
 
-impl<'a, A: Allocator> BlockOwner for Scoped<'a, A> {
-    fn owns_block(&self, block: &Block) -> bool {
-        let ptr = block.ptr();
+impl<'a, A: Allocator> CapacityAware for Scoped<'a, A> {
+    /// Nothing can be allocated while the allocator is scoped.
+    /// The reserve isn't counted.
+    fn remaining(&self) -> Option<usize> {
+        if self.is_scoped() {
+            Some(0)
+        } else {
+            Some((self.limit() as usize).saturating_sub(self.current.get() as usize))
+        }
+    }
+}
+
+impl<'a, A: Allocator> Reset for Scoped<'a, A> {
+    /// Resets the allocator, reclaiming everything allocated from it at once,
+    /// after running the callbacks registered with `on_reset`.
+    ///
+    /// This does nothing if the allocator is currently scoped.
+    unsafe fn reset(&self) {
+        if !self.is_scoped() {
+            let finalizers = mem::replace(&mut *self.finalizers.borrow_mut(), Vec::new());
+            for finalizer in finalizers {
+                finalizer();
+            }
+            self.current.set(self.start);
+            self.back.set(self.ceiling());
+            self.markers.borrow_mut().clear();
+        }
+    }
+}
 
+impl<'a, A: Allocator> BlockOwner for Scoped<'a, A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
         ptr >= self.start && ptr <= self.end
     }
+
+    /// Resets the allocator. Returns an error if it is currently scoped.
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        if self.is_scoped() {
+            Err(Error::AllocatorSpecific("Called deallocate_all on already scoped allocator.".into()))
+        } else {
+            self.reset();
+            Ok(())
+        }
+    }
 }
 
 impl<'a, A: Allocator> Drop for Scoped<'a, A> {
@@ -171,68 +756,1125 @@ impl<'a, A: Allocator> Drop for Scoped<'a, A> {
     }
 }
 
+// A `Scoped` only shares its parent with other threads, by reference.
+// Its own memory moves along with it, and moving it to another thread by any
+// safe means (spawning, channels, mutexes) makes its contents visible there.
 unsafe impl<'a, A: 'a + Allocator + Sync> Send for Scoped<'a, A> {}
 
-#[cfg(test)]
-mod tests {
-    use super::super::*;
+/// A view of a `Scoped` allocator which enforces last-in, first-out deallocation.
+///
+/// `Scoped` only reclaims a freed block if it was the most recent allocation, so values
+/// freed in exactly the reverse order they were allocated are all reclaimed, making it a
+/// stack allocator. Freeing in any other order silently leaves memory unreclaimed until
+/// the end of the scope. In debug builds, a `ScopeStack` panics on such out-of-order
+/// frees instead, to catch broken stack discipline early.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Allocator, Scoped, ScopeStack};
+///
+/// let alloc = Scoped::new(64).unwrap();
+/// let stack = ScopeStack::new(&alloc);
+/// let first = stack.allocate(1u64).unwrap();
+/// let second = stack.allocate(2u64).unwrap();
+/// drop(second);
+/// drop(first);
+/// ```
+pub struct ScopeStack<'a, 'parent: 'a, A: 'parent + Allocator> {
+    scoped: &'a Scoped<'parent, A>,
+    live: RefCell<Vec<*mut u8>>,
+}
 
-    #[test]
-    #[should_panic]
-    fn use_outer() {
-        let alloc = Scoped::new(4).unwrap();
-        let mut outer_val = alloc.allocate(0i32).unwrap();
-        alloc.scope(|_inner| {
-            // using outer allocator is dangerous and should fail.
-                 outer_val = alloc.allocate(1i32).unwrap();
-             })
-             .unwrap();
+impl<'a, 'parent, A: Allocator> ScopeStack<'a, 'parent, A> {
+    /// Creates a new `ScopeStack` allocating from the `Scoped` supplied.
+    pub fn new(scoped: &'a Scoped<'parent, A>) -> Self {
+        ScopeStack {
+            scoped: scoped,
+            live: RefCell::new(Vec::new()),
+        }
     }
 
-    #[test]
-    fn scope_scope() {
-        let alloc = Scoped::new(64).unwrap();
-        let _ = alloc.allocate(0).unwrap();
-        alloc.scope(|inner| {
-                 let _ = inner.allocate(32);
-                 inner.scope(|bottom| {
-                          let _ = bottom.allocate(23);
-                      })
-                      .unwrap();
-             })
-             .unwrap();
+    /// The number of blocks allocated through this which haven't been freed yet.
+    pub fn depth(&self) -> usize {
+        self.live.borrow().len()
     }
+}
 
-    #[test]
-    fn out_of_memory() {
-        // allocate more memory than the allocator has.
-        let alloc = Scoped::new(0).unwrap();
-        let (err, _) = alloc.allocate(1i32).err().unwrap();
-        assert_eq!(err, Error::OutOfMemory);
+unsafe impl<'a, 'parent, A: Allocator> Allocator for ScopeStack<'a, 'parent, A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        match self.scoped.allocate_raw(size, align) {
+            Ok(block) => {
+                if !block.is_empty() {
+                    self.live.borrow_mut().push(block.ptr());
+                }
+                Ok(block)
+            }
+            Err(err) => Err(err),
+        }
     }
 
-    #[test]
-    fn placement_in() {
-        let alloc = Scoped::new(8_000_000).unwrap();
-        // this would smash the stack otherwise.
-        let _big = in alloc.make_place().unwrap() { [0u8; 8_000_000] };
+    unsafe fn reallocate_raw<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+        let old_ptr = block.ptr();
+        match self.scoped.reallocate_raw(block, new_size) {
+            Ok(new_block) => {
+                let mut live = self.live.borrow_mut();
+                if let Some(idx) = live.iter().rposition(|&ptr| ptr == old_ptr) {
+                    live.remove(idx);
+                }
+                if !new_block.is_empty() {
+                    live.push(new_block.ptr());
+                }
+                Ok(new_block)
+            }
+            Err(err) => Err(err),
+        }
     }
 
-    #[test]
-    fn owning() {
-        let alloc = Scoped::new(64).unwrap();
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if block.is_empty() {
+            return;
+        }
 
-        let val = alloc.allocate(1i32).unwrap();
-        assert!(alloc.owns(&val));
+        {
+            let mut live = self.live.borrow_mut();
+            debug_assert!(live.last() == Some(&block.ptr()),
+                          "Block {:?} freed out of order; it won't be reclaimed.",
+                          block.ptr());
+            if let Some(idx) = live.iter().rposition(|&ptr| ptr == block.ptr()) {
+                live.remove(idx);
+            }
+        }
+        self.scoped.deallocate_raw(block)
+    }
+}
 
-        alloc.scope(|inner| {
-                 let in_val = inner.allocate(2i32).unwrap();
+/// A linear allocator whose memory reads as zero when allocated, zeroing its buffer
+/// lazily, a page at a time.
+///
+/// Zeroing a large arena up front costs as much as the arena is big, however little of it
+/// gets used. This instead zeroes each page the first time an allocation reaches into it,
+/// tracking which pages have been zeroed in a bitmap, so arenas which are only partly
+/// used only pay for what they use. Further allocations within a page already zeroed
+/// cost nothing extra. Pages are counted from the start of the buffer, and are a
+/// logical granule: they need not line up with the operating system's pages.
+///
+/// Like `Scoped`, only the most recent allocation is reclaimed when freed. The bytes it
+/// covered are zeroed again, eagerly within the page it started in and lazily beyond it.
+pub struct LazyZeroed<'parent, A: 'parent + Allocator> {
+    allocator: &'parent A,
+    start: *mut u8,
+    current: Cell<*mut u8>,
+    end: *mut u8,
+    page_size: usize,
+    // one bit per page, set once the page has been zeroed.
+    zeroed: RefCell<Vec<u64>>,
+    pages_zeroed: Cell<usize>,
+    allocations: Cell<usize>,
+    failures: Cell<usize>,
+    // callbacks to run at the next reset.
+    finalizers: RefCell<Vec<Box<FnOnce() + Send + 'parent>>>,
+}
+
+impl LazyZeroed<'static, HeapAllocator> {
+    /// Creates a new `LazyZeroed` backed by `size` bytes from the heap, which are
+    /// zeroed in pages of `page_size` bytes.
+    ///
+    /// # Panics
+    /// Panics if `page_size` isn't a power of two.
+    pub fn new(size: usize, page_size: usize) -> Result<Self, Error> {
+        LazyZeroed::new_from(HEAP, size, page_size)
+    }
+}
+
+impl<'parent, A: Allocator> LazyZeroed<'parent, A> {
+    /// Creates a new `LazyZeroed` backed by `size` bytes from the allocator supplied,
+    /// which are zeroed in pages of `page_size` bytes.
+    ///
+    /// # Panics
+    /// Panics if `page_size` isn't a power of two.
+    pub fn new_from(alloc: &'parent A, size: usize, page_size: usize) -> Result<Self, Error> {
+        assert!(page_size.is_power_of_two(), "Page size must be a power of two.");
+        let pages = (size + page_size - 1) / page_size;
+        match unsafe { alloc.allocate_raw(size, mem::align_of::<usize>()) } {
+            Ok(block) => Ok(LazyZeroed {
+                allocator: alloc,
+                start: block.ptr(),
+                current: Cell::new(block.ptr()),
+                end: unsafe { block.ptr().offset(block.size() as isize) },
+                page_size: page_size,
+                zeroed: RefCell::new(vec![0; (pages + 63) / 64]),
+                pages_zeroed: Cell::new(0),
+                allocations: Cell::new(0),
+                failures: Cell::new(0),
+                finalizers: RefCell::new(Vec::new()),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The size of the pages this zeroes at a time.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The number of times a page has been zeroed so far.
+    pub fn pages_zeroed(&self) -> usize {
+        self.pages_zeroed.get()
+    }
+
+    /// Registers a callback to run at the next `reset`, just before the memory is reclaimed.
+    /// See `Scoped::on_reset`.
+    pub fn on_reset<F: FnOnce() + Send + 'parent>(&self, f: F) {
+        self.finalizers.borrow_mut().push(Box::new(f));
+    }
+
+    // the index of the page holding the byte at ptr.
+    fn page_of(&self, ptr: *mut u8) -> usize {
+        (ptr as usize - self.start as usize) / self.page_size
+    }
+
+    // zeroes every page overlapping `ptr..ptr + size` which hasn't been yet.
+    // pages which haven't been zeroed hold no live allocations, so they can be
+    // zeroed whole.
+    unsafe fn touch(&self, ptr: *mut u8, size: usize) {
+        let mut zeroed = self.zeroed.borrow_mut();
+        for page in self.page_of(ptr)..(self.page_of(ptr.offset(size as isize - 1)) + 1) {
+            let (word, bit) = (page / 64, 1 << (page % 64));
+            if zeroed[word] & bit == 0 {
+                let page_start = self.start.offset((page * self.page_size) as isize);
+                let len = cmp::min(self.page_size, self.end as usize - page_start as usize);
+                ptr::write_bytes(page_start, 0, len);
+                zeroed[word] |= bit;
+                self.pages_zeroed.set(self.pages_zeroed.get() + 1);
+            }
+        }
+    }
+
+    // moves the current pointer back to `ptr`, restoring the bytes after it to zero:
+    // those in the page `ptr` lies in now, and the rest once they are touched again.
+    unsafe fn rewind(&self, ptr: *mut u8) {
+        let old = self.current.get();
+        if ptr >= old {
+            return;
+        }
+
+        let first_page = self.page_of(ptr);
+        let mut cleared = first_page;
+        if (ptr as usize - self.start as usize) % self.page_size != 0 {
+            // the page is still in use below ptr, so zero what was freed of it now.
+            let page_end = self.start as usize + (first_page + 1) * self.page_size;
+            let len = cmp::min(page_end, old as usize) - ptr as usize;
+            ptr::write_bytes(ptr, 0, len);
+            cleared += 1;
+        }
+
+        let mut zeroed = self.zeroed.borrow_mut();
+        for page in cleared..(self.page_of(old.offset(-1)) + 1) {
+            zeroed[page / 64] &= !(1 << (page % 64));
+        }
+        self.current.set(ptr);
+    }
+}
+
+unsafe impl<'a, A: Allocator> Allocator for LazyZeroed<'a, A> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        if size == 0 {
+            return Ok(Block::empty());
+        }
+
+        let aligned_ptr = super::align_forward(self.current.get(), align);
+        match (self.end as usize).checked_sub(size) {
+            Some(last) if aligned_ptr as usize <= last => {
+                self.touch(aligned_ptr, size);
+                self.current.set(aligned_ptr.offset(size as isize));
+                self.allocations.set(self.allocations.get() + 1);
+                Ok(Block::new(aligned_ptr, size, align))
+            }
+            _ => {
+                self.failures.set(self.failures.get() + 1);
+                Err(Error::OutOfMemory {
+                    requested: size,
+                    align: align,
+                })
+            }
+        }
+    }
+
+    /// Only the most recent allocation is resized in place. Bytes added to a block
+    /// aren't necessarily zero.
+    unsafe fn reallocate_raw<'b>(&'b self, block: Block<'b>, new_size: usize) -> Result<Block<'b>, (Error, Block<'b>)> {
+        if new_size == 0 {
+            Ok(Block::empty())
+        } else if block.is_empty() {
+            Err((Error::UnsupportedAlignment, block))
+        } else if block.ptr().offset(block.size() as isize) == self.current.get() {
+            if new_size <= self.end as usize - block.ptr() as usize {
+                if new_size < block.size() {
+                    self.rewind(block.ptr().offset(new_size as isize));
+                } else {
+                    self.touch(block.ptr(), new_size);
+                    self.current.set(block.ptr().offset(new_size as isize));
+                }
+                Ok(Block::new(block.ptr(), new_size, block.align()))
+            } else {
+                let align = block.align();
+                Err((Error::OutOfMemory {
+                    requested: new_size,
+                    align: align,
+                }, block))
+            }
+        } else {
+            let align = block.align();
+            super::copy_reallocate(self, block, new_size, align)
+        }
+    }
+
+    /// Only the most recent allocation can grow in place.
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        !block.is_empty() && block.ptr().offset(block.size() as isize) == self.current.get() &&
+        new_size <= self.end as usize - block.ptr() as usize
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if !block.is_empty() && block.ptr().offset(block.size() as isize) == self.current.get() {
+            self.rewind(block.ptr());
+        }
+    }
+
+    /// Newly allocated memory always reads as zero.
+    fn provides_zeroed(&self) -> bool {
+        true
+    }
+
+    fn report(&self) -> Report {
+        let mut report = Report::new("LazyZeroed");
+        report.bytes_outstanding = self.current.get() as usize - self.start as usize;
+        report.allocations = self.allocations.get();
+        report.failures = self.failures.get();
+        report
+    }
+}
+
+impl<'a, A: Allocator> CapacityAware for LazyZeroed<'a, A> {
+    fn remaining(&self) -> Option<usize> {
+        Some(self.end as usize - self.current.get() as usize)
+    }
+}
+
+impl<'a, A: Allocator> Reset for LazyZeroed<'a, A> {
+    /// Reclaims everything allocated at once, after running the callbacks registered with
+    /// `on_reset`. Every page will be zeroed again the next time it's touched.
+    unsafe fn reset(&self) {
+        let finalizers = mem::replace(&mut *self.finalizers.borrow_mut(), Vec::new());
+        for finalizer in finalizers {
+            finalizer();
+        }
+        self.current.set(self.start);
+        for word in self.zeroed.borrow_mut().iter_mut() {
+            *word = 0;
+        }
+    }
+}
+
+impl<'a, A: Allocator> BlockOwner for LazyZeroed<'a, A> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        ptr >= self.start && ptr <= self.end
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.reset();
+        Ok(())
+    }
+}
+
+impl<'a, A: Allocator> Drop for LazyZeroed<'a, A> {
+    fn drop(&mut self) {
+        let size = self.end as usize - self.start as usize;
+        if size > 0 {
+            unsafe {
+                self.allocator
+                    .deallocate_raw(Block::new(self.start, size, mem::align_of::<usize>()))
+            }
+        }
+    }
+}
+
+unsafe impl<'a, A: 'a + Allocator + Sync> Send for LazyZeroed<'a, A> {}
+
+/// A linear allocator whose `N`-byte buffer is stored inline, rather than
+/// allocated from a parent.
+///
+/// This makes it possible to keep a small scratch arena on the stack or inside another
+/// structure without touching the heap at all. Allocations borrow from `&self`, so the
+/// arena can't be moved while any of them are alive; it's free to move once they're gone.
+/// Positions are tracked as offsets into the buffer for that reason.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Allocator, InlineScoped};
+///
+/// let scratch = InlineScoped::<64>::new();
+/// let val = scratch.allocate(5u32).unwrap();
+/// assert_eq!(*val, 5);
+/// ```
+pub struct InlineScoped<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    current: Cell<usize>,
+}
+
+impl<const N: usize> InlineScoped<N> {
+    /// Creates a new, empty `InlineScoped`.
+    ///
+    /// This is a `const fn`, so the buffer is zeroed at compile time when used to
+    /// initialize a `thread_local!`. An `InlineScoped` can't be shared between threads,
+    /// so a `static` arena must be an `AtomicInlineScoped` instead.
+    pub const fn new() -> Self {
+        InlineScoped {
+            buffer: UnsafeCell::new([0; N]),
+            current: Cell::new(0),
+        }
+    }
+
+    /// The number of bytes which have been allocated, including padding.
+    pub fn allocated(&self) -> usize {
+        self.current.get()
+    }
+
+    /// Checks that the allocator is consistent: that it hasn't allocated past its buffer.
+    #[cfg(any(test, feature = "invariants"))]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if self.current.get() > N {
+            return Err(format!("Allocated {} bytes out of {}.", self.current.get(), N));
+        }
+        Ok(())
+    }
+
+    fn start(&self) -> *mut u8 {
+        self.buffer.get() as *mut u8
+    }
+}
+
+unsafe impl<const N: usize> Allocator for InlineScoped<N> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        if size == 0 {
+            return Ok(Block::empty());
+        }
+
+        let current_ptr = self.start().offset(self.current.get() as isize);
+        let aligned_ptr = super::align_forward(current_ptr, align);
+        let offset = aligned_ptr as usize - self.start() as usize;
+
+        match offset.checked_add(size) {
+            Some(end) if end <= N => {
+                self.current.set(end);
+                Ok(Block::new(aligned_ptr, size, align))
+            }
+            _ => Err(Error::OutOfMemory {
+                requested: size,
+                align: align,
+            }),
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        if block.is_empty() {
+            Err((Error::UnsupportedAlignment, block))
+        } else if new_size == 0 {
+            self.deallocate_raw(block);
+            Ok(Block::empty())
+        } else if block.ptr().offset(block.size() as isize) ==
+                  self.start().offset(self.current.get() as isize) {
+            // the last block can be resized in place.
+            let offset = block.ptr() as usize - self.start() as usize;
+            if offset + new_size <= N {
+                self.current.set(offset + new_size);
+                Ok(Block::new(block.ptr(), new_size, block.align()))
+            } else {
+                let align = block.align();
+                Err((Error::OutOfMemory {
+                    requested: new_size,
+                    align: align,
+                }, block))
+            }
+        } else {
+            let align = block.align();
+            super::copy_reallocate(self, block, new_size, align)
+        }
+    }
+
+    /// Only the most recent allocation can grow in place.
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        let offset = block.ptr() as usize - self.start() as usize;
+        !block.is_empty() && offset + block.size() == self.current.get() && offset + new_size <= N
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if block.is_empty() {
+            return;
+        }
+        // only the last allocation can be reclaimed before a reset.
+        if block.ptr().offset(block.size() as isize) ==
+           self.start().offset(self.current.get() as isize) {
+            self.current.set(block.ptr() as usize - self.start() as usize);
+        }
+    }
+}
+
+impl<const N: usize> CapacityAware for InlineScoped<N> {
+    fn remaining(&self) -> Option<usize> {
+        Some(N - self.current.get())
+    }
+}
+
+impl<const N: usize> Reset for InlineScoped<N> {
+    /// Resets the allocator, reclaiming everything allocated from it at once.
+    ///
+    /// Unlike `Scoped`, this has no `on_reset` callbacks: it keeps all of its state
+    /// inline, so it has nowhere to store them without touching the heap.
+    unsafe fn reset(&self) {
+        self.current.set(0);
+    }
+}
+
+impl<const N: usize> BlockOwner for InlineScoped<N> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        let ptr = ptr as usize;
+        let start = self.start() as usize;
+
+        ptr >= start && ptr < start + N
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.reset();
+        Ok(())
+    }
+}
+
+/// A linear allocator with an inline buffer, like `InlineScoped`, which may be shared
+/// between threads. Allocation bumps an atomic offset, so this can be used from a `static`
+/// without any runtime setup.
+///
+/// Only the most recent allocation can be reclaimed or resized in place; everything
+/// else is only reclaimed by `reset`.
+///
+/// # Examples
+/// ```rust
+/// use allocators::{Allocator, AtomicInlineScoped};
+///
+/// static ARENA: AtomicInlineScoped<65536> = AtomicInlineScoped::new();
+///
+/// let val = ARENA.allocate(5u32).unwrap();
+/// assert_eq!(*val, 5);
+/// ```
+pub struct AtomicInlineScoped<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    current: AtomicUsize,
+}
+
+impl<const N: usize> AtomicInlineScoped<N> {
+    /// Creates a new, empty `AtomicInlineScoped`.
+    pub const fn new() -> Self {
+        AtomicInlineScoped {
+            buffer: UnsafeCell::new([0; N]),
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    /// The number of bytes which have been allocated, including padding.
+    pub fn allocated(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    fn start(&self) -> *mut u8 {
+        self.buffer.get() as *mut u8
+    }
+
+    // moves the offset from `old` to `new`, if nothing else moved it first.
+    fn bump_from(&self, old: usize, new: usize) -> bool {
+        self.current.compare_exchange(old, new, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    }
+}
+
+unsafe impl<const N: usize> Allocator for AtomicInlineScoped<N> {
+    unsafe fn allocate_raw(&self, size: usize, align: usize) -> Result<Block, Error> {
+        if let Err(err) = super::check_align(align) {
+            return Err(err);
+        }
+        if size == 0 {
+            return Ok(Block::empty());
+        }
+
+        let mut current = self.current.load(Ordering::Relaxed);
+        loop {
+            let aligned_ptr = super::align_forward(self.start().offset(current as isize), align);
+            let offset = aligned_ptr as usize - self.start() as usize;
+
+            match offset.checked_add(size) {
+                Some(end) if end <= N => {
+                    if self.bump_from(current, end) {
+                        return Ok(Block::new(aligned_ptr, size, align));
+                    }
+                }
+                _ => return Err(Error::OutOfMemory {
+                    requested: size,
+                    align: align,
+                }),
+            }
+            current = self.current.load(Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn reallocate_raw<'a>(&'a self, block: Block<'a>, new_size: usize) -> Result<Block<'a>, (Error, Block<'a>)> {
+        if block.is_empty() {
+            return Err((Error::UnsupportedAlignment, block));
+        } else if new_size == 0 {
+            self.deallocate_raw(block);
+            return Ok(Block::empty());
+        }
+
+        let offset = block.ptr() as usize - self.start() as usize;
+        if offset + new_size <= N && self.bump_from(offset + block.size(), offset + new_size) {
+            // this was the last block, so it was resized in place.
+            Ok(Block::new(block.ptr(), new_size, block.align()))
+        } else {
+            let align = block.align();
+            super::copy_reallocate(self, block, new_size, align)
+        }
+    }
+
+    /// Only the most recent allocation can grow in place. Another thread
+    /// may allocate in the meantime, so the answer may be out of date.
+    unsafe fn can_grow_in_place(&self, block: &Block, new_size: usize) -> bool {
+        let offset = block.ptr() as usize - self.start() as usize;
+        !block.is_empty() && offset + block.size() == self.allocated() && offset + new_size <= N
+    }
+
+    unsafe fn deallocate_raw(&self, block: Block) {
+        if block.is_empty() {
+            return;
+        }
+        // only the last allocation can be reclaimed before a reset.
+        let offset = block.ptr() as usize - self.start() as usize;
+        self.bump_from(offset + block.size(), offset);
+    }
+}
+
+impl<const N: usize> CapacityAware for AtomicInlineScoped<N> {
+    fn remaining(&self) -> Option<usize> {
+        Some(N - self.allocated())
+    }
+}
+
+impl<const N: usize> Reset for AtomicInlineScoped<N> {
+    /// Resets the allocator, reclaiming everything allocated from it at once.
+    ///
+    /// Unlike `Scoped`, this has no `on_reset` callbacks: it keeps all of its state
+    /// inline, so it has nowhere to store them without touching the heap.
+    unsafe fn reset(&self) {
+        self.current.store(0, Ordering::Release);
+    }
+}
+
+impl<const N: usize> BlockOwner for AtomicInlineScoped<N> {
+    fn owns_ptr(&self, ptr: *mut u8) -> bool {
+        let ptr = ptr as usize;
+        let start = self.start() as usize;
+
+        ptr >= start && ptr < start + N
+    }
+
+    unsafe fn deallocate_all(&self) -> Result<(), Error> {
+        self.reset();
+        Ok(())
+    }
+}
+
+// blocks are handed out by atomically bumping the offset,
+// so no two threads are ever given the same memory.
+unsafe impl<const N: usize> Sync for AtomicInlineScoped<N> {}
+
+/// A point in the history of a `Scoped` allocator.
+///
+/// Moving a `Scoped` or its contents to another thread through a channel, mutex, or
+/// `thread::spawn` already ensures that everything written before the move is visible
+/// to the receiving thread. Epochs are for handing memory over by less structured means,
+/// like a shared reference to the epoch itself: taking an epoch stores the number of
+/// bytes allocated with release ordering, and `acquire` loads it with acquire ordering.
+/// Another thread reading the epoch through `acquire` therefore sees everything written
+/// to the allocator's memory before the epoch was taken.
+///
+/// After `acquire`, the first `allocated()` bytes of the allocator's buffer, as they were
+/// when the epoch was taken, may be safely read.
+#[derive(Debug)]
+pub struct Epoch(AtomicUsize);
+
+impl Epoch {
+    /// The number of bytes which had been allocated when this epoch was taken.
+    ///
+    /// This doesn't synchronize with the thread which took the epoch; use `acquire` for that.
+    pub fn allocated(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Makes everything published by this epoch visible to the current thread,
+    /// returning the number of bytes which had been allocated when it was taken.
+    pub fn acquire(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::slice;
+
+    use super::super::*;
+
+    #[test]
+    fn scope_alignment() {
+        let alloc = Scoped::new(256).unwrap().with_scope_alignment(64);
+        let _first = alloc.allocate(1u8).unwrap();
+        alloc.scope(|inner| {
+                 let val = inner.allocate(2u8).unwrap();
+                 assert_eq!(&*val as *const u8 as usize % 64, 0);
+                 assert!(inner.padding_bytes() > 0);
+             })
+             .unwrap();
+        assert!(alloc.padding_bytes() > 0);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn lazy_zeroed() {
+        let alloc = LazyZeroed::new(256, 64).unwrap();
+        unsafe {
+            let first = alloc.allocate_raw(16, 8).unwrap();
+            assert_eq!(alloc.pages_zeroed(), 1);
+            ptr::write_bytes(first.ptr(), 0xff, 16);
+
+            // reusing the page doesn't zero it again.
+            let second = alloc.allocate_raw(16, 8).unwrap();
+            assert_eq!(alloc.pages_zeroed(), 1);
+            assert!(slice::from_raw_parts(second.ptr(), 16).iter().all(|&b| b == 0));
+            ptr::write_bytes(second.ptr(), 0xff, 16);
+
+            // freed bytes read as zero when allocated again.
+            alloc.deallocate_raw(second);
+            let third = alloc.allocate_raw(100, 8).unwrap();
+            assert_eq!(alloc.pages_zeroed(), 2);
+            assert!(slice::from_raw_parts(third.ptr(), 100).iter().all(|&b| b == 0));
+            ptr::write_bytes(third.ptr(), 0xff, 100);
+            assert!(slice::from_raw_parts(first.ptr(), 16).iter().all(|&b| b == 0xff));
+
+            alloc.reset();
+            let fourth = alloc.allocate_raw(256, 8).unwrap();
+            assert_eq!(alloc.pages_zeroed(), 6);
+            assert!(slice::from_raw_parts(fourth.ptr(), 256).iter().all(|&b| b == 0));
+        }
+    }
+
+    #[test]
+    fn scope_unwind() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let alloc = Scoped::new(64).unwrap();
+        let _outer = alloc.allocate(0u64).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            alloc.scope(|inner| {
+                     let _val = inner.allocate(1u64).unwrap();
+                     let _ = in inner.make_place().unwrap() { panic!("boom") };
+                 })
+                 .unwrap();
+        }));
+        assert!(result.is_err());
+
+        // the scope was left behind along with everything in it.
+        assert!(!alloc.is_scoped());
+        assert_eq!(alloc.remaining(), Some(56));
+        assert!(alloc.allocate(2u64).is_ok());
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn remaining_for_align() {
+        let alloc = Scoped::new(64).unwrap();
+        let _byte = alloc.allocate(1u8).unwrap();
+        assert_eq!(alloc.remaining_for_align(1), 63);
+        assert_eq!(alloc.remaining_for_align(8), 56);
+        for &align in &[1, 2, 8, 32] {
+            let size = alloc.remaining_for_align(align);
+            unsafe {
+                assert!(alloc.allocate_raw(size + 1, align).is_err());
+                let block = alloc.allocate_raw(size, align).unwrap();
+                assert_eq!(alloc.remaining(), Some(0));
+                alloc.deallocate_raw(block);
+            }
+        }
+        alloc.scope(|_| assert_eq!(alloc.remaining_for_align(1), 0)).unwrap();
+
+        // nothing can be allocated with these alignments.
+        for &align in &[0, 3, 48, MAX_ALIGN << 1] {
+            assert_eq!(alloc.remaining_for_align(align), 0);
+        }
+    }
+
+    #[test]
+    fn split_n() {
+        use std::thread;
+
+        let mut alloc = Scoped::new(256).unwrap();
+        let _first = unsafe { alloc.allocate_raw(1, 1).unwrap() };
+        {
+            let parts = alloc.split_n(4);
+            for pair in parts.windows(2) {
+                assert_eq!(pair[0].remaining(), Some(56));
+                assert!(pair[0].end <= pair[1].start);
+            }
+
+            thread::scope(|threads| {
+                for (i, part) in parts.into_iter().enumerate() {
+                    threads.spawn(move || {
+                        let mut vals = Vec::new();
+                        for j in 0..7 {
+                            vals.push(part.allocate(i * 10 + j).unwrap());
+                        }
+                        assert!(part.allocate(0usize).is_err());
+                        assert_eq!(*vals[6], i * 10 + 6);
+                        part.check_invariants().unwrap();
+                    });
+                }
+            });
+        }
+        assert_eq!(alloc.remaining(), Some(255));
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn on_reset() {
+        use std::panic::{self, AssertUnwindSafe};
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let alloc = Scoped::new(64).unwrap();
+        let counter = runs.clone();
+        alloc.on_reset(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        unsafe { alloc.reset() };
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        unsafe { alloc.reset() };
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // ending an inner scope counts as resetting it.
+        alloc.scope(|inner| {
+                 let counter = runs.clone();
+                 inner.on_reset(move || {
+                     counter.fetch_add(1, Ordering::SeqCst);
+                 });
+             })
+             .unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        // so does unwinding out of one.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            alloc.scope(|inner| {
+                     let counter = runs.clone();
+                     inner.on_reset(move || {
+                         counter.fetch_add(1, Ordering::SeqCst);
+                     });
+                     panic!("boom");
+                 })
+                 .unwrap();
+        }));
+        assert!(result.is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        let lazy = LazyZeroed::new(64, 16).unwrap();
+        let counter = runs.clone();
+        lazy.on_reset(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        unsafe { lazy.reset() };
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn shrink_last_block() {
+        let alloc = Scoped::new(32).unwrap();
+        unsafe {
+            let block = alloc.allocate_raw(32, 8).unwrap();
+            let ptr = block.ptr();
+            let block = alloc.reallocate_raw(block, 8).ok().unwrap();
+            assert_eq!(block.ptr(), ptr);
+            assert_eq!(alloc.remaining(), Some(24));
+
+            let next = alloc.allocate_raw(24, 8).unwrap();
+            assert_eq!(next.ptr(), ptr.offset(8));
+        }
+    }
+
+    #[test]
+    fn double_ended() {
+        let alloc = Scoped::new(64).unwrap();
+        let mut blocks = Vec::new();
+        unsafe {
+            while !alloc.is_full() {
+                blocks.push(alloc.allocate_front(8, 8).unwrap());
+                blocks.push(alloc.allocate_back(8, 8).unwrap());
+            }
+            assert!(alloc.allocate_front(1, 1).is_err());
+            assert!(alloc.allocate_back(1, 1).is_err());
+        }
+        assert_eq!(blocks.len(), 8);
+        for pair in blocks.chunks(2) {
+            assert!(pair[0].ptr() < pair[1].ptr());
+        }
+        assert_eq!(alloc.report().bytes_outstanding, 64);
+        alloc.check_invariants().unwrap();
+
+        // the most recent block from the back can be freed.
+        unsafe { alloc.deallocate_raw(blocks.pop().unwrap()) };
+        assert!(!alloc.is_full());
+        unsafe { alloc.reset() };
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    fn double_ended_scope() {
+        let alloc = Scoped::new(64).unwrap();
+        let back = unsafe { alloc.allocate_back(32, 8).unwrap() };
+        alloc.scope(|inner| {
+                 assert_eq!(inner.remaining(), Some(32));
+                 unsafe { inner.allocate_back(16, 8).unwrap() };
+                 inner.check_invariants().unwrap();
+             })
+             .unwrap();
+        assert_eq!(alloc.remaining(), Some(32));
+        unsafe { alloc.deallocate_raw(back) };
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    fn reserve() {
+        let alloc = Scoped::with_reserve(32, 16).unwrap();
+        assert_eq!(alloc.remaining(), Some(16));
+        let _filled = alloc.allocate([0u8; 16]).unwrap();
+        assert!(alloc.allocate(0u8).is_err());
+        unsafe {
+            assert!(alloc.allocate_reserved(16, 1).is_ok());
+            assert!(alloc.allocate_reserved(1, 1).is_err());
+        }
+        assert!(Scoped::with_reserve(16, 32).is_err());
+    }
+
+    #[test]
+    fn reserve_from() {
+        let outer = Scoped::new(64).unwrap();
+        let alloc = Scoped::with_reserve_from(&outer, 32, 16).unwrap();
+        assert_eq!(outer.remaining(), Some(32));
+        assert_eq!(alloc.remaining(), Some(16));
+        let _filled = alloc.allocate([0u8; 16]).unwrap();
+        assert!(alloc.allocate(0u8).is_err());
+        unsafe {
+            assert!(alloc.allocate_reserved(16, 1).is_ok());
+        }
+
+        // nothing is taken from the backing allocator if the reserve doesn't fit.
+        assert!(Scoped::with_reserve_from(&outer, 16, 32).is_err());
+        assert_eq!(outer.remaining(), Some(32));
+    }
+
+    #[test]
+    fn shrink_reserved() {
+        let alloc = Scoped::with_reserve(32, 16).unwrap();
+        let _filled = alloc.allocate([0u8; 16]).unwrap();
+        unsafe {
+            let block = alloc.allocate_reserved(16, 1).unwrap();
+            let block = alloc.reallocate_raw(block, 8).unwrap();
+            assert_eq!(block.size(), 8);
+            // growing may not reach into the reserve, however.
+            let block = alloc.reallocate_raw(block, 12).unwrap_err().1;
+            alloc.deallocate_raw(block);
+        }
+        assert_eq!(alloc.remaining(), Some(0));
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn use_outer() {
+        let alloc = Scoped::new(4).unwrap();
+        let mut outer_val = alloc.allocate(0i32).unwrap();
+        alloc.scope(|_inner| {
+            // using outer allocator is dangerous and should fail.
+                 outer_val = alloc.allocate(1i32).unwrap();
+             })
+             .unwrap();
+    }
+
+    #[test]
+    fn scope_scope() {
+        let alloc = Scoped::new(64).unwrap();
+        let _ = alloc.allocate(0).unwrap();
+        alloc.scope(|inner| {
+                 let _ = inner.allocate(32);
+                 inner.scope(|bottom| {
+                          let _ = bottom.allocate(23);
+                      })
+                      .unwrap();
+             })
+             .unwrap();
+    }
+
+    #[test]
+    fn scope_scope_invariants() {
+        let alloc = Scoped::new(64).unwrap();
+        let _ = alloc.allocate(0).unwrap();
+        alloc.scope(|inner| {
+                 let _ = inner.allocate(32);
+                 inner.scope(|bottom| {
+                          let _ = bottom.allocate(23);
+                          bottom.check_invariants().unwrap();
+                      })
+                      .unwrap();
+                 inner.check_invariants().unwrap();
+             })
+             .unwrap();
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn out_of_memory() {
+        // allocate more memory than the allocator has.
+        let alloc = Scoped::new(0).unwrap();
+        let (err, _) = alloc.allocate(1i32).err().unwrap();
+        assert_eq!(err,
+                   Error::OutOfMemory {
+                       requested: 4,
+                       align: 4,
+                   });
+        assert_eq!(err.to_string(), "Allocator out of memory: failed to allocate 4 bytes aligned to 4.");
+    }
+
+    #[test]
+    fn placement_in() {
+        let alloc = Scoped::new(8_000_000).unwrap();
+        // this would smash the stack otherwise.
+        let _big = in alloc.make_place().unwrap() { [0u8; 8_000_000] };
+    }
+
+    #[test]
+    fn owning() {
+        let alloc = Scoped::new(64).unwrap();
+
+        let val = alloc.allocate(1i32).unwrap();
+        assert!(alloc.owns(&val));
+
+        alloc.scope(|inner| {
+                 let in_val = inner.allocate(2i32).unwrap();
                  assert!(inner.owns(&in_val));
                  assert!(!inner.owns(&val));
              })
              .unwrap();
     }
 
+    #[test]
+    fn send_filled() {
+        use std::thread;
+        let alloc = Scoped::new(64).unwrap();
+        let addr = unsafe {
+            let block = alloc.allocate_raw(16, 8).unwrap();
+            for i in 0..16 {
+                *block.ptr().offset(i) = i as u8;
+            }
+            block.ptr() as usize
+        };
+        let epoch = alloc.epoch().unwrap();
+        assert_eq!(epoch.allocated(), 16);
+
+        let sum = thread::spawn(move || {
+                      assert_eq!(epoch.acquire(), 16);
+                      let sum = (0..16).map(|i| unsafe { *(addr as *const u8).offset(i) } as usize).sum::<usize>();
+                      drop(alloc);
+                      sum
+                  })
+                      .join()
+                      .unwrap();
+        assert_eq!(sum, 120);
+    }
+
+    // whether each page of the region is backed by physical memory.
+    #[cfg(all(feature = "os", target_os = "linux"))]
+    fn resident(ptr: *mut u8, len: usize, page_size: usize) -> Vec<bool> {
+        use libc;
+
+        let mut pages = vec![0u8; (len + page_size - 1) / page_size];
+        let res = unsafe { libc::mincore(ptr as *mut libc::c_void, len, pages.as_mut_ptr()) };
+        assert_eq!(res, 0);
+        pages.iter().map(|&page| page & 1 != 0).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "os")]
+    fn decommit_tail() {
+        let alloc = Scoped::new(1 << 20).unwrap();
+        let _ = alloc.allocate([1u8; 1000]).unwrap();
+        alloc.decommit();
+
+        let mut val = alloc.allocate([0u8; 16 * 1024]).unwrap();
+        val[8 * 1024] = 1;
+        assert_eq!(val[8 * 1024], 1);
+    }
+
+    #[test]
+    #[cfg(all(feature = "os", target_os = "linux"))]
+    fn decommit_releases_tail() {
+        let mmap = MmapAllocator::new();
+        let page_size = mmap.page_size();
+        let size = 64 * page_size;
+        let alloc = Scoped::new_from(&mmap, size).unwrap();
+        let head = alloc.allocate([1u8; 1000]).unwrap();
+        alloc.prefault();
+        assert!(resident(alloc.start, size, page_size).iter().all(|&page| page));
+
+        // the page holding the head stays, and every page after it is released.
+        alloc.decommit();
+        let pages = resident(alloc.start, size, page_size);
+        assert!(pages[0]);
+        assert!(pages[1..].iter().all(|&page| !page));
+        assert!(head.iter().all(|&byte| byte == 1));
+
+        // on Linux, released pages read as zeroes.
+        let rest = unsafe { alloc.allocate_raw(size - page_size, page_size).unwrap() };
+        let bytes = unsafe { ::std::slice::from_raw_parts(rest.ptr(), rest.size()) };
+        assert!(bytes.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    #[cfg(all(feature = "os", target_os = "linux"))]
+    fn prefault() {
+        let mmap = MmapAllocator::new();
+        let page_size = mmap.page_size();
+        let size = 64 * page_size;
+        let alloc = Scoped::new_from(&mmap, size).unwrap();
+        let first = alloc.allocate(0x0123_4567_89ab_cdefu64).unwrap();
+
+        alloc.prefault();
+        assert!(resident(alloc.start, size, page_size).iter().all(|&page| page));
+        assert_eq!(*first, 0x0123_4567_89ab_cdef);
+
+        let rest = unsafe { alloc.allocate_raw(size - 8, 8).unwrap() };
+        unsafe { *rest.ptr().offset((size - 9) as isize) = 1 };
+    }
+
     #[test]
     fn mutex_sharing() {
         use std::thread;
@@ -247,4 +1889,186 @@ mod tests {
             });
         }
     }
+
+    #[test]
+    fn inline_scoped() {
+        let alloc = InlineScoped::<16>::new();
+        {
+            let a = alloc.allocate([1u8; 10]).unwrap();
+            let b = alloc.allocate([2u8; 6]).unwrap();
+            assert!(alloc.owns_block(&unsafe { b.as_block() }));
+            assert_eq!(alloc.allocated(), 16);
+            assert!(alloc.allocate(3u8).is_err());
+            assert_eq!((a[9], b[5]), (1, 2));
+        }
+        // both were freed in reverse order, so all the space is reclaimed.
+        assert_eq!(alloc.allocated(), 0);
+        alloc.check_invariants().unwrap();
+    }
+
+    #[test]
+    fn scope_retaining() {
+        let alloc = Scoped::new(8).unwrap();
+        let ptr = alloc.scope_retaining(|inner| {
+                           let val = inner.allocate(7u64).unwrap();
+                           let ptr = &*val as *const u64;
+                           ::std::mem::forget(val);
+                           ptr
+                       })
+                       .unwrap();
+
+        // the memory is still in use.
+        assert!(alloc.allocate(0u64).is_err());
+        assert_eq!(unsafe { *ptr }, 7);
+    }
+
+    #[test]
+    fn allocate_unchecked() {
+        let alloc = Scoped::new(8).unwrap();
+        let block = unsafe { alloc.allocate_raw_unchecked(8, 8).unwrap() };
+        assert_eq!(block.size(), 8);
+        assert!(unsafe { alloc.allocate_raw_unchecked(1, 1) }.is_err());
+    }
+
+    #[test]
+    fn push_and_pop_scopes() {
+        let alloc = Scoped::new(64).unwrap();
+        let _outer = alloc.allocate(0u64).unwrap();
+        alloc.push_scope().unwrap();
+        ::std::mem::forget(alloc.allocate([0u8; 32]).unwrap());
+        alloc.push_scope().unwrap();
+        ::std::mem::forget(alloc.allocate([0u8; 24]).unwrap());
+        assert!(alloc.allocate(0u8).is_err());
+
+        unsafe {
+            alloc.pop_scope().unwrap();
+            assert_eq!(alloc.epoch().unwrap().allocated(), 40);
+            alloc.pop_scope().unwrap();
+            assert_eq!(alloc.epoch().unwrap().allocated(), 8);
+            assert!(alloc.pop_scope().is_err());
+        }
+    }
+
+    #[test]
+    fn static_arena() {
+        use std::thread;
+
+        static ARENA: AtomicInlineScoped<4096> = AtomicInlineScoped::new();
+
+        let threads: Vec<_> = (0..4u8)
+                                  .map(|i| {
+                                      thread::spawn(move || {
+                                          let vals: Vec<_> = (0..16)
+                                                                 .map(|_| ARENA.allocate(i).unwrap())
+                                                                 .collect();
+                                          assert!(vals.iter().all(|val| **val == i));
+                                          ::std::mem::forget(vals);
+                                      })
+                                  })
+                                  .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(ARENA.allocated(), 4 * 16);
+    }
+
+    #[test]
+    fn padding_bytes() {
+        let alloc = Scoped::new(64).unwrap();
+        let _a = alloc.allocate(1u8).unwrap();
+        let _b = alloc.allocate(2u64).unwrap();
+        let _c = alloc.allocate(3u8).unwrap();
+        let _d = alloc.allocate(4u32).unwrap();
+        // the buffer is aligned for a `usize`, so the padding is predictable.
+        assert_eq!(alloc.padding_bytes(), 7 + 3);
+    }
+
+    #[test]
+    fn promote() {
+        let alloc = Scoped::new(64).unwrap();
+        let promoted = alloc.scope(|inner| {
+                                let val = inner.allocate([5u8; 32]).unwrap();
+                                let promoted = inner.promote(&alloc, val).ok().unwrap();
+                                // the promoted value is out of the scope's reach.
+                                assert_eq!(inner.remaining(), Some(32));
+                                promoted
+                            })
+                            .unwrap();
+
+        // the rest of the scope's memory was reclaimed, but the value lives on in the parent.
+        assert!(alloc.owns(&promoted));
+        assert_eq!(alloc.remaining(), Some(32));
+        assert!(alloc.allocate([0u8; 33]).is_err());
+        let _filler = alloc.allocate([0u8; 32]).unwrap();
+        assert_eq!(promoted[31], 5);
+        alloc.check_invariants().unwrap();
+        drop(_filler);
+        drop(promoted);
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    fn promote_elsewhere() {
+        let alloc = Scoped::new(64).unwrap();
+        let other = Scoped::new(64).unwrap();
+        alloc.scope(|inner| {
+                 let val = inner.allocate(1u32).unwrap();
+                 let val = inner.promote(&other, val).err().unwrap().1;
+                 // promoting out of a nested scope skips a level, which isn't allowed.
+                 inner.scope(|nested| {
+                          let val = nested.allocate(2u32).unwrap();
+                          assert!(nested.promote(&alloc, val).is_err());
+                      })
+                      .unwrap();
+                 assert_eq!(*val, 1);
+             })
+             .unwrap();
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    fn scope_stack_reclaims() {
+        let alloc = Scoped::new(64).unwrap();
+        {
+            let stack = ScopeStack::new(&alloc);
+            let first = stack.allocate(1u64).unwrap();
+            let second = stack.allocate([2u8; 40]).unwrap();
+            assert_eq!(stack.depth(), 2);
+            assert_eq!(alloc.remaining(), Some(16));
+            drop(second);
+            drop(first);
+            assert_eq!(stack.depth(), 0);
+        }
+        assert_eq!(alloc.remaining(), Some(64));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "out of order")]
+    fn scope_stack_out_of_order() {
+        let alloc = Scoped::new(64).unwrap();
+        let stack = ScopeStack::new(&alloc);
+        let first = stack.allocate(1u64).unwrap();
+        let _second = stack.allocate(2u64).unwrap();
+        drop(first);
+    }
+
+    #[test]
+    fn can_grow_in_place() {
+        let alloc = Scoped::new(64).unwrap();
+        unsafe {
+            let first = alloc.allocate_raw(8, 8).unwrap();
+            assert!(alloc.can_grow_in_place(&first, 64));
+            assert!(!alloc.can_grow_in_place(&first, 65));
+
+            let second = alloc.allocate_raw(8, 8).unwrap();
+            assert!(!alloc.can_grow_in_place(&first, 16));
+            assert!(alloc.can_grow_in_place(&second, 56));
+
+            let inline = InlineScoped::<16>::new();
+            let block = inline.allocate_raw(8, 1).unwrap();
+            assert!(inline.can_grow_in_place(&block, 16));
+            assert!(!inline.can_grow_in_place(&block, 17));
+        }
+    }
 }
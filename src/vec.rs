@@ -0,0 +1,340 @@
+//! A growable vector backed by a custom allocator.
+
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::slice;
+use std::usize;
+
+use super::{AllocBox, Allocator, Block, Error};
+
+/// The growth factor used by new vectors.
+pub const DEFAULT_GROWTH_FACTOR: f64 = 2.0;
+
+/// A contiguous, growable array whose buffer is drawn from a custom allocator.
+///
+/// Growing the buffer goes through the allocator's `reallocate_raw`, so how expensive it is
+/// depends on the allocator. The growth factor can be tuned accordingly: a large factor
+/// makes sense when reallocation always copies, while the default of 2 suits the heap.
+pub struct AllocVec<'a, T, A: 'a + ?Sized + Allocator> {
+    block: Block<'a>,
+    len: usize,
+    cap: usize,
+    growth_factor: f64,
+    allocator: &'a A,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, A: ?Sized + Allocator> AllocVec<'a, T, A> {
+    /// Creates a new, empty vector. No memory is allocated until elements are pushed.
+    pub fn new(alloc: &'a A) -> Self {
+        AllocVec {
+            block: Block::empty(),
+            len: 0,
+            cap: if mem::size_of::<T>() == 0 { usize::MAX } else { 0 },
+            growth_factor: DEFAULT_GROWTH_FACTOR,
+            allocator: alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a new, empty vector with room for at least `capacity` elements.
+    pub fn with_capacity(capacity: usize, alloc: &'a A) -> Result<Self, Error> {
+        let mut vec = AllocVec::new(alloc);
+        match vec.reserve(capacity) {
+            Ok(()) => Ok(vec),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sets the factor the capacity is multiplied by when the vector needs to grow.
+    ///
+    /// # Panics
+    /// Panics if the factor isn't greater than 1.
+    pub fn set_growth_factor(&mut self, factor: f64) {
+        assert!(factor > 1.0, "Growth factor must be greater than 1.");
+        self.growth_factor = factor;
+    }
+
+    /// Get the growth factor of this vector.
+    pub fn growth_factor(&self) -> f64 {
+        self.growth_factor
+    }
+
+    /// Get the number of elements in this vector.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether this vector is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the number of elements this vector can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Pushes a value onto the end of the vector, growing it if necessary.
+    ///
+    /// If the vector needs to grow and the allocator fails, the value is given back.
+    pub fn push(&mut self, val: T) -> Result<(), (Error, T)> {
+        if self.len == self.cap {
+            if let Err(err) = self.grow(1) {
+                return Err((err, val));
+            }
+        }
+
+        unsafe { ptr::write(self.as_mut_ptr().offset(self.len as isize), val) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last value in the vector and returns it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            unsafe { Some(ptr::read(self.as_mut_ptr().offset(self.len as isize))) }
+        }
+    }
+
+    /// Ensures there is room for at least `additional` more elements, growing by exactly
+    /// as much as needed.
+    pub fn reserve(&mut self, additional: usize) -> Result<(), Error> {
+        match self.len.checked_add(additional) {
+            Some(required) if required > self.cap => self.resize(required),
+            Some(_) => Ok(()),
+            None => Err(Error::OutOfMemory {
+                requested: usize::MAX,
+                align: mem::align_of::<T>(),
+            }),
+        }
+    }
+
+    /// Shrinks the buffer down to the length of the vector.
+    pub fn shrink_to_fit(&mut self) -> Result<(), Error> {
+        if self.cap > self.len && mem::size_of::<T>() != 0 {
+            let len = self.len;
+            self.resize(len)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Converts the vector into a boxed slice, shrinking the buffer down to its length.
+    ///
+    /// This releases any excess capacity through `reallocate_raw`, which may move the
+    /// elements. Allocators which can't give back part of a block, like `FreeList`, keep
+    /// it in place. If the allocator fails to shrink the buffer, the vector is given back.
+    pub fn into_boxed_slice(mut self) -> Result<AllocBox<'a, [T], A>, (Error, Self)> {
+        if let Err(err) = self.shrink_to_fit() {
+            return Err((err, self));
+        }
+
+        let boxed = unsafe { AllocBox::from_raw_parts(self.buffer(), self.len, self.allocator) };
+        mem::forget(self);
+        Ok(boxed)
+    }
+
+    /// Get a raw pointer to the vector's buffer.
+    pub fn as_ptr(&self) -> *const T {
+        self.buffer()
+    }
+
+    /// Get a raw mutable pointer to the vector's buffer.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.buffer()
+    }
+
+    fn buffer(&self) -> *mut T {
+        if self.block.is_empty() {
+            mem::align_of::<T>() as *mut T
+        } else {
+            self.block.ptr() as *mut T
+        }
+    }
+
+    // grow the buffer according to the growth factor, leaving room for at least
+    // `additional` more elements.
+    fn grow(&mut self, additional: usize) -> Result<(), Error> {
+        let required = match self.len.checked_add(additional) {
+            Some(required) => required,
+            None => return Err(Error::OutOfMemory {
+                requested: usize::MAX,
+                align: mem::align_of::<T>(),
+            }),
+        };
+        let scaled = (self.cap as f64 * self.growth_factor).ceil() as usize;
+        let new_cap = cmp::max(cmp::max(required, scaled), 1);
+        self.resize(new_cap)
+    }
+
+    // reallocate the buffer to hold exactly `new_cap` elements.
+    fn resize(&mut self, new_cap: usize) -> Result<(), Error> {
+        if mem::size_of::<T>() == 0 {
+            return Ok(());
+        }
+
+        let new_size = match new_cap.checked_mul(mem::size_of::<T>()) {
+            Some(size) => size,
+            None => return Err(Error::OutOfMemory {
+                requested: usize::MAX,
+                align: mem::align_of::<T>(),
+            }),
+        };
+
+        let old = mem::replace(&mut self.block, Block::empty());
+        let result = unsafe {
+            if old.is_empty() {
+                match self.allocator.allocate_raw(new_size, mem::align_of::<T>()) {
+                    Ok(block) => Ok(block),
+                    Err(err) => Err((err, old)),
+                }
+            } else if new_cap > self.cap {
+                self.allocator.reallocate_to_usable(old, new_size)
+            } else {
+                self.allocator.reallocate_raw(old, new_size)
+            }
+        };
+
+        match result {
+            Ok(block) => {
+                // growing may have turned up more room than was asked for.
+                self.cap = cmp::max(new_cap, block.size() / mem::size_of::<T>());
+                self.block = block;
+                Ok(())
+            }
+            Err((err, old)) => {
+                self.block = old;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Deref for AllocVec<'a, T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.buffer(), self.len) }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> DerefMut for AllocVec<'a, T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.buffer(), self.len) }
+    }
+}
+
+impl<'a, T, A: ?Sized + Allocator> Drop for AllocVec<'a, T, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(&mut **self as *mut [T]);
+            self.allocator.deallocate_raw(mem::replace(&mut self.block, Block::empty()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut vec = AllocVec::new(HEAP);
+        for i in 0..100 {
+            vec.push(i).ok().unwrap();
+        }
+        assert_eq!(vec.len(), 100);
+        assert_eq!(vec[42], 42);
+        assert_eq!(vec.pop(), Some(99));
+    }
+
+    #[test]
+    fn growth_factor() {
+        let mut vec = AllocVec::with_capacity(2, HEAP).unwrap();
+        vec.set_growth_factor(4.0);
+        for i in 0..3u8 {
+            vec.push(i).ok().unwrap();
+        }
+        assert_eq!(vec.capacity(), 8);
+    }
+
+    #[test]
+    fn into_boxed_slice() {
+        let mut vec = AllocVec::with_capacity(16, HEAP).unwrap();
+        for i in 0..3u32 {
+            vec.push(i).ok().unwrap();
+        }
+        let boxed = vec.into_boxed_slice().ok().unwrap();
+        assert_eq!(boxed.allocated_size(), 12);
+        assert_eq!(&*boxed, &[0, 1, 2]);
+
+        let empty = AllocVec::<u32, _>::with_capacity(4, HEAP).unwrap().into_boxed_slice().ok().unwrap();
+        assert_eq!(empty.allocated_size(), 0);
+    }
+
+    #[test]
+    fn into_boxed_slice_in_place() {
+        let alloc = FreeList::new(64, 1).unwrap();
+        let mut vec = AllocVec::with_capacity(16, &alloc).unwrap();
+        for i in 0..3u32 {
+            vec.push(i).ok().unwrap();
+        }
+        let ptr = vec.as_ptr();
+        let boxed = vec.into_boxed_slice().ok().unwrap();
+        assert_eq!(boxed.as_ptr(), ptr);
+        assert_eq!(&*boxed, &[0, 1, 2]);
+        assert_eq!(alloc.free(), 0);
+        drop(boxed);
+        assert_eq!(alloc.free(), 1);
+    }
+
+    #[test]
+    fn grows_into_usable_size() {
+        let alloc = FreeList::new(64, 1).unwrap();
+        let mut vec = AllocVec::with_capacity(1, &alloc).unwrap();
+        vec.push(0u32).ok().unwrap();
+        vec.push(1).ok().unwrap();
+        // growing found the whole block, so there's no need to grow again.
+        assert_eq!(vec.capacity(), 16);
+        for i in 2..16 {
+            vec.push(i).ok().unwrap();
+        }
+        assert_eq!(vec[15], 15);
+        assert!(vec.push(16).is_err());
+    }
+
+    #[test]
+    fn reserve_and_shrink() {
+        let mut vec = AllocVec::new(HEAP);
+        vec.reserve(64).unwrap();
+        assert_eq!(vec.capacity(), 64);
+        vec.push(1u32).ok().unwrap();
+        vec.shrink_to_fit().unwrap();
+        assert_eq!(vec.capacity(), 1);
+        assert_eq!(&*vec, &[1]);
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let alloc = Scoped::new(16).unwrap();
+        let mut vec = AllocVec::with_capacity(4, &alloc).unwrap();
+        for i in 0..4u32 {
+            vec.push(i).ok().unwrap();
+        }
+        let (err, val) = vec.push(4).err().unwrap();
+        assert_eq!(err,
+                   Error::OutOfMemory {
+                       requested: 32,
+                       align: 4,
+                   });
+        assert_eq!(val, 4);
+        assert_eq!(&*vec, &[0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,91 @@
+//! Replays fixed and random sequences of operations against the allocators.
+
+extern crate allocators;
+
+use allocators::{DoubleBuffer, FreeList, InlineScoped, Scoped};
+use allocators::replay::{replay, Op};
+
+// a deterministic stream of operations from a xorshift generator.
+fn random_ops(seed: u64, len: usize, max_size: usize) -> Vec<Op> {
+    let mut state = seed;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+
+    (0..len)
+        .map(|_| match next() % 3 {
+            0 => Op::Alloc {
+                size: next() % max_size,
+                align_log2: (next() % 5) as u8,
+            },
+            1 => Op::Dealloc(next()),
+            _ => Op::Realloc(next(), 1 + next() % max_size),
+        })
+        .collect()
+}
+
+#[test]
+fn scoped_reclamation() {
+    // shrinking and freeing the most recent block must leave the rest intact.
+    let ops = [Op::Alloc { size: 32, align_log2: 3 },
+               Op::Alloc { size: 32, align_log2: 3 },
+               Op::Realloc(1, 8),
+               Op::Alloc { size: 24, align_log2: 3 },
+               Op::Dealloc(2),
+               Op::Realloc(1, 40),
+               Op::Dealloc(0)];
+    let alloc = Scoped::new(128).unwrap();
+    replay(&alloc, &ops).unwrap();
+}
+
+#[test]
+fn freelist_merging() {
+    // growing a block into the free blocks after it, then splitting it again.
+    let ops = [Op::Alloc { size: 16, align_log2: 3 },
+               Op::Realloc(0, 48),
+               Op::Alloc { size: 16, align_log2: 3 },
+               Op::Realloc(0, 16),
+               Op::Alloc { size: 16, align_log2: 3 },
+               Op::Alloc { size: 16, align_log2: 3 },
+               Op::Dealloc(0),
+               Op::Dealloc(0)];
+    let scoped = Scoped::new(64).unwrap();
+    let alloc = FreeList::new_from(&scoped, 16, 4).unwrap().with_ascending_order();
+    replay(&alloc, &ops).unwrap();
+}
+
+#[test]
+fn scoped_random() {
+    for seed in 1..32 {
+        let alloc = Scoped::new(1024).unwrap();
+        replay(&alloc, &random_ops(seed, 256, 96)).unwrap();
+    }
+}
+
+#[test]
+fn freelist_random() {
+    for seed in 1..32 {
+        let scoped = Scoped::new(1024).unwrap();
+        let alloc = FreeList::new_from(&scoped, 32, 32).unwrap().with_ascending_order();
+        replay(&alloc, &random_ops(seed, 256, 96)).unwrap();
+    }
+}
+
+#[test]
+fn inline_random() {
+    for seed in 1..32 {
+        let alloc = InlineScoped::<1024>::new();
+        replay(&alloc, &random_ops(seed, 256, 96)).unwrap();
+    }
+}
+
+#[test]
+fn double_buffer_random() {
+    for seed in 1..32 {
+        let alloc = DoubleBuffer::new(512).unwrap();
+        replay(&alloc, &random_ops(seed, 256, 96)).unwrap();
+    }
+}